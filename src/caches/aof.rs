@@ -0,0 +1,310 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FsyncPolicy {
+	/// Fsync after every logged write
+	Always,
+	/// Fsync at most once per second
+	Everysec,
+	/// Leave flushing to the operating system
+	Never,
+}
+
+/// A single mutation recorded in the append-only log.
+///
+/// Writes are logged with their resulting value and absolute expiration,
+/// so replaying the same entry twice always yields the same state. Changes
+/// to a single element of a collection are logged the same way.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AofEntry {
+	Set {
+		k: String,
+		v: serde_json::Value,
+		e: u128,
+	},
+	Del {
+		k: String,
+	},
+	Expire {
+		k: String,
+		e: u128,
+	},
+	Hset {
+		k: String,
+		f: String,
+		v: serde_json::Value,
+	},
+	Hdel {
+		k: String,
+		f: String,
+	},
+	/// Element `i` added in front of a list, see `list::List` for the ids.
+	Lpush {
+		k: String,
+		i: i64,
+		v: serde_json::Value,
+	},
+	Rpush {
+		k: String,
+		i: i64,
+		v: serde_json::Value,
+	},
+	Lpop {
+		k: String,
+		i: i64,
+	},
+	Rpop {
+		k: String,
+		i: i64,
+	},
+	/// Elements outside the ids `f` to `l` removed from a list.
+	Ltrim {
+		k: String,
+		f: i64,
+		l: i64,
+	},
+	Sadd {
+		k: String,
+		m: String,
+	},
+	Srem {
+		k: String,
+		m: String,
+	},
+	Zadd {
+		k: String,
+		m: String,
+		s: f64,
+	},
+	Zrem {
+		k: String,
+		m: String,
+	},
+	Flush,
+}
+
+pub struct Aof {
+	file: File,
+	policy: FsyncPolicy,
+	dirty: bool,
+	pub size: u64,
+}
+
+impl Aof {
+	pub fn open(path: &Path, policy: FsyncPolicy) -> io::Result<Self> {
+		let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+		let size: u64 = file.metadata()?.len();
+		Ok(Aof {
+			file,
+			policy,
+			dirty: false,
+			size,
+		})
+	}
+
+	pub fn append(&mut self, entry: &AofEntry) -> io::Result<()> {
+		let mut line: Vec<u8> = serde_json::to_vec(entry)?;
+		line.push(b'\n');
+		self.file.write_all(&line)?;
+		self.size += line.len() as u64;
+		self.dirty = true;
+
+		if self.policy == FsyncPolicy::Always {
+			self.sync()?;
+		}
+		Ok(())
+	}
+
+	pub fn sync(&mut self) -> io::Result<()> {
+		if self.dirty {
+			self.file.sync_data()?;
+			self.dirty = false;
+		}
+		Ok(())
+	}
+
+	/// Returns a handle that the caller can fsync without holding the cache lock.
+	/// Called once per second, only does something under the `everysec` policy.
+	pub fn pending_sync(&mut self) -> Option<io::Result<File>> {
+		if self.policy != FsyncPolicy::Everysec || !self.dirty {
+			return None;
+		}
+		self.dirty = false;
+		Some(self.file.try_clone())
+	}
+
+	/// Called when the fsync of a `pending_sync` handle failed, so the next one
+	/// tries again instead of leaving the entries unsynced.
+	pub fn sync_failed(&mut self) {
+		self.dirty = true;
+	}
+
+	/// Moves the current log to `old_path` and continues with an empty one, so a
+	/// snapshot can be written in the background while new writes keep being logged.
+	/// Entries of an unfinished previous rotation are kept by appending to `old_path`.
+	pub fn rotate(&mut self, path: &Path, old_path: &Path) -> io::Result<()> {
+		self.file.sync_data()?;
+		if old_path.exists() {
+			let mut old: File = OpenOptions::new().append(true).open(old_path)?;
+			io::copy(&mut File::open(path)?, &mut old)?;
+			old.sync_all()?;
+			self.file.set_len(0)?;
+		} else {
+			fs::rename(path, old_path)?;
+			self.file = OpenOptions::new().create(true).append(true).open(path)?;
+		}
+		self.size = 0;
+		self.dirty = false;
+		Ok(())
+	}
+}
+
+/// Reads every complete entry of the log at `path` and passes it to `apply`.
+///
+/// A torn entry at the end of the file (a crash in the middle of a write) is
+/// cut off so that new entries are appended after the last valid one.
+pub fn replay(path: &Path, mut apply: impl FnMut(AofEntry)) -> io::Result<()> {
+	let file: File = match File::open(path) {
+		Ok(file) => file,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+		Err(err) => return Err(err),
+	};
+
+	let mut reader: BufReader<File> = BufReader::new(file);
+	let mut line: Vec<u8> = Vec::new();
+	let mut valid_len: u64 = 0;
+
+	loop {
+		line.clear();
+		let n: usize = reader.read_until(b'\n', &mut line)?;
+		if n == 0 {
+			break;
+		}
+		if line.last() != Some(&b'\n') {
+			eprintln!(
+				"Append-only log {} ends with an incomplete entry, truncating it",
+				path.display()
+			);
+			break;
+		}
+		match serde_json::from_slice::<AofEntry>(&line) {
+			Ok(entry) => apply(entry),
+			Err(err) => {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					format!(
+						"Corrupt entry in {} at byte {}: {}",
+						path.display(),
+						valid_len,
+						err
+					),
+				));
+			}
+		}
+		valid_len += n as u64;
+	}
+
+	let file_len: u64 = fs::metadata(path)?.len();
+	if valid_len < file_len {
+		OpenOptions::new()
+			.write(true)
+			.open(path)?
+			.set_len(valid_len)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::path::PathBuf;
+
+	fn log_path(name: &str) -> PathBuf {
+		let path: PathBuf =
+			std::env::temp_dir().join(format!("rabbit-kv-aof-{}-{}", std::process::id(), name));
+		fs::remove_file(&path).ok();
+		path
+	}
+
+	fn replayed(path: &Path) -> io::Result<Vec<serde_json::Value>> {
+		let mut entries: Vec<serde_json::Value> = Vec::new();
+		replay(path, |entry| {
+			entries.push(serde_json::to_value(entry).unwrap())
+		})?;
+		Ok(entries)
+	}
+
+	fn set(key: &str, value: i64) -> AofEntry {
+		AofEntry::Set {
+			k: key.to_string(),
+			v: value.into(),
+			e: 0,
+		}
+	}
+
+	#[test]
+	fn replays_appended_entries_in_order() {
+		let path: PathBuf = log_path("order");
+		let mut aof: Aof = Aof::open(&path, FsyncPolicy::Never).unwrap();
+		aof.append(&set("a", 1)).unwrap();
+		aof.append(&AofEntry::Del { k: "a".to_string() }).unwrap();
+		aof.append(&AofEntry::Flush).unwrap();
+		assert_eq!(aof.size, fs::metadata(&path).unwrap().len());
+
+		let entries: Vec<serde_json::Value> = replayed(&path).unwrap();
+		assert_eq!(
+			entries,
+			vec![
+				serde_json::json!({ "SET": { "k": "a", "v": 1, "e": 0 } }),
+				serde_json::json!({ "DEL": { "k": "a" } }),
+				serde_json::json!("FLUSH"),
+			]
+		);
+		fs::remove_file(&path).ok();
+	}
+
+	#[test]
+	fn missing_log_replays_nothing() {
+		let path: PathBuf = log_path("missing");
+		assert!(replayed(&path).unwrap().is_empty());
+	}
+
+	#[test]
+	fn cuts_off_torn_entry_at_the_end() {
+		let path: PathBuf = log_path("torn");
+		let mut aof: Aof = Aof::open(&path, FsyncPolicy::Never).unwrap();
+		aof.append(&set("a", 1)).unwrap();
+		let valid_len: u64 = aof.size;
+		aof.file.write_all(br#"{"SET":{"k":"b","#).unwrap();
+
+		assert_eq!(replayed(&path).unwrap().len(), 1);
+		assert_eq!(fs::metadata(&path).unwrap().len(), valid_len);
+
+		// New entries follow the last valid one.
+		let mut aof: Aof = Aof::open(&path, FsyncPolicy::Never).unwrap();
+		aof.append(&set("c", 3)).unwrap();
+		assert_eq!(replayed(&path).unwrap().len(), 2);
+		fs::remove_file(&path).ok();
+	}
+
+	#[test]
+	fn fails_on_corrupt_entry_in_the_middle() {
+		let path: PathBuf = log_path("corrupt");
+		let mut aof: Aof = Aof::open(&path, FsyncPolicy::Never).unwrap();
+		aof.append(&set("a", 1)).unwrap();
+		aof.file.write_all(b"not json\n").unwrap();
+		aof.append(&set("b", 2)).unwrap();
+		let len: u64 = fs::metadata(&path).unwrap().len();
+
+		let err: io::Error = replayed(&path).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+		// Nothing is cut off, the entries after the corrupt one are still there.
+		assert_eq!(fs::metadata(&path).unwrap().len(), len);
+		fs::remove_file(&path).ok();
+	}
+}
@@ -1,135 +1,1656 @@
 use indexmap::IndexMap;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::hash::BuildHasher;
 use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::futures::Notified;
+use tokio::sync::{broadcast, Notify};
 
-use super::stats::Stats;
+use super::aof::{self, Aof, AofEntry, FsyncPolicy};
+use super::data::{Collection, Data, DataType};
+use super::events::{Event, KeyEvent, EVENT_BUFFER};
+use super::eviction::{
+	entry_size, lfu_decay, lfu_increment, EvictionPolicy, Limits, EVICTION_SAMPLE, LFU_INIT,
+};
+use super::hash::Hash;
+use super::list::List;
+use super::set::Set;
+use super::snapshot::{self, Items, SnapshotOptions};
+use super::stats::{SaveStatus, ShardCounters, ShardStats, Stats};
+use super::zset::SortedSet;
+use crate::error::ErrorCode;
 use crate::utils::current_time;
 
 #[derive(Debug, Serialize, Deserialize)]
-struct CacheItemSmall {
+pub struct CacheItemSmall {
 	pub v: serde_json::Value,
 	pub e: u128,
+	#[serde(default, skip_serializing_if = "DataType::is_value")]
+	pub t: DataType,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Expiration of keys that never expire.
+pub const NO_EXPIRATION: u128 = 0;
+
+#[derive(Debug)]
 pub struct CacheItem {
 	pub expiration: u128,
-	pub value: serde_json::Value,
+	pub value: Data,
+	/// Changes whenever the value or the expiration does and only ever grows, see
+	/// `Cache::versions`.
+	pub version: u64,
+	/// Insertion order across all shards, used when `preserve_order` is set.
+	sequence: u64,
+	/// Last access time, used by the LRU and LFU eviction policies.
+	/// Atomic because reads only hold the shard lock in shared mode.
+	accessed: AtomicU64,
+	/// Logarithmic access counter, used by the LFU eviction policy.
+	frequency: AtomicU8,
 }
 
-#[derive(Clone, Default)]
-pub struct Cache {
+impl CacheItem {
+	pub fn new(value: Data, expiration: u128) -> Self {
+		CacheItem {
+			expiration,
+			value,
+			version: 0,
+			sequence: 0,
+			accessed: AtomicU64::new(current_time() as u64),
+			frequency: AtomicU8::new(LFU_INIT),
+		}
+	}
+
+	pub fn is_expired(&self, current_time: u128) -> bool {
+		expired(self.expiration, current_time)
+	}
+
+	/// Remaining time to live in milliseconds, `None` for keys without expiration.
+	pub fn ttl(&self, current_time: u128) -> Option<u128> {
+		match self.expiration {
+			NO_EXPIRATION => None,
+			expiration => Some(expiration.saturating_sub(current_time)),
+		}
+	}
+
+	fn accessed(&self) -> u128 {
+		self.accessed.load(Ordering::Relaxed) as u128
+	}
+
+	fn frequency(&self, current_time: u128) -> u8 {
+		lfu_decay(
+			self.frequency.load(Ordering::Relaxed),
+			self.accessed(),
+			current_time,
+		)
+	}
+
+	fn touch(&self, current_time: u128) {
+		let frequency: u8 = lfu_increment(self.frequency(current_time));
+		self.frequency.store(frequency, Ordering::Relaxed);
+		self.accessed.store(current_time as u64, Ordering::Relaxed);
+	}
+}
+
+fn expired(expiration: u128, current_time: u128) -> bool {
+	expiration != NO_EXPIRATION && expiration <= current_time
+}
+
+/// One independently locked part of the keyspace.
+#[derive(Default)]
+pub struct Shard {
 	pub cache: IndexMap<String, CacheItem>,
-	pub stats: Stats,
+	/// Keys with an expiration ordered by it, kept in sync with `cache`.
+	expirations: BTreeSet<(u128, String)>,
+	pub stats: ShardCounters,
+	/// Estimated memory used by the entries of this shard, see `eviction::entry_size`.
+	pub used_memory: u64,
+	/// Keys removed from this shard so far. Removed keys leave no version behind,
+	/// so this tells whether a missing key may have existed in between.
+	pub removals: u64,
+	preserve_order: bool,
+}
+
+impl Shard {
+	pub fn get(&self, key: &str) -> Option<&CacheItem> {
+		self.stats.reads.fetch_add(1, Ordering::Relaxed);
+		let current_time: u128 = current_time();
+		self
+			.cache
+			.get(key)
+			.filter(|item| !item.is_expired(current_time))
+			.inspect(|item| item.touch(current_time))
+	}
+
+	/// The collection of type `C` stored under `key`, `None` when the key does not
+	/// exist. Fails with `WrongType` when the key holds something else.
+	pub fn collection<C: Collection>(&self, key: &str) -> Result<Option<&C>, ErrorCode> {
+		match self.get(key) {
+			Some(item) => C::from_data(&item.value)
+				.map(Some)
+				.ok_or(ErrorCode::WrongType),
+			None => Ok(None),
+		}
+	}
+
+	/// Like `get`, without counting it as a read.
+	fn live(&self, key: &str) -> Option<&CacheItem> {
+		self
+			.cache
+			.get(key)
+			.filter(|item| !item.is_expired(current_time()))
+	}
+
+	/// Removes up to `limit` expired keys, earliest expiration first,
+	/// and returns them.
+	fn expire(&mut self, limit: usize) -> Vec<String> {
+		let current_time: u128 = current_time();
+		let mut expired: Vec<String> = Vec::new();
+		while expired.len() < limit {
+			match self.expirations.first() {
+				Some((expiration, _)) if *expiration <= current_time => {}
+				_ => break,
+			}
+			let (_, key) = self.expirations.pop_first().unwrap();
+			self.remove_from_map(&key);
+			expired.push(key);
+		}
+		self
+			.stats
+			.expired
+			.fetch_add(expired.len() as u64, Ordering::Relaxed);
+		expired
+	}
+
+	/// Picks the key to evict next, never one of `exclude` (the keys being written).
+	fn eviction_victim(&self, policy: EvictionPolicy, exclude: &[&str]) -> Option<String> {
+		let current_time: u128 = current_time();
+		let victim: Option<&String> = match policy {
+			EvictionPolicy::Noeviction => return None,
+			EvictionPolicy::VolatileTtl => {
+				return self
+					.expirations
+					.iter()
+					.map(|(_, key)| key)
+					.find(|key| !exclude.contains(&key.as_str()))
+					.cloned()
+			}
+			EvictionPolicy::Random => self.sample(exclude).first().map(|(key, _)| *key),
+			EvictionPolicy::AllkeysLru => self
+				.sample(exclude)
+				.into_iter()
+				.min_by_key(|(_, item)| item.accessed())
+				.map(|(key, _)| key),
+			EvictionPolicy::AllkeysLfu => self
+				.sample(exclude)
+				.into_iter()
+				.min_by_key(|(_, item)| (item.frequency(current_time), item.accessed()))
+				.map(|(key, _)| key),
+		};
+
+		// A sample can miss when only a few keys exist, fall back to any other key.
+		victim
+			.or_else(|| {
+				self
+					.cache
+					.keys()
+					.find(|key| !exclude.contains(&key.as_str()))
+			})
+			.cloned()
+	}
+
+	fn sample(&self, exclude: &[&str]) -> Vec<(&String, &CacheItem)> {
+		if self.cache.is_empty() {
+			return Vec::new();
+		}
+		let mut rng = rand::rng();
+		(0..EVICTION_SAMPLE)
+			.filter_map(|_| self.cache.get_index(rng.random_range(0..self.cache.len())))
+			.filter(|(key, _)| !exclude.contains(&key.as_str()))
+			.collect()
+	}
+
+	fn insert(&mut self, key: String, mut item: CacheItem) {
+		if let Some(old) = self.cache.get(&key) {
+			self.expirations.remove(&(old.expiration, key.clone()));
+			self.used_memory -= entry_size(&key, &old.value);
+			item.sequence = old.sequence;
+		}
+		self.used_memory += entry_size(&key, &item.value);
+		if item.expiration != NO_EXPIRATION {
+			self.expirations.insert((item.expiration, key.clone()));
+		}
+		self.cache.insert(key, item);
+	}
+
+	fn set_expiration(&mut self, key: &str, expiration: u128) {
+		if let Some(item) = self.cache.get_mut(key) {
+			self.expirations.remove(&(item.expiration, key.to_string()));
+			if expiration != NO_EXPIRATION {
+				self.expirations.insert((expiration, key.to_string()));
+			}
+			item.expiration = expiration;
+		}
+	}
+
+	fn remove(&mut self, key: &str) {
+		if let Some(item) = self.remove_from_map(key) {
+			self.expirations.remove(&(item.expiration, key.to_string()));
+		}
+	}
+
+	fn remove_from_map(&mut self, key: &str) -> Option<CacheItem> {
+		let item: Option<CacheItem> = if self.preserve_order {
+			self.cache.shift_remove(key)
+		} else {
+			self.cache.swap_remove(key)
+		};
+		if let Some(item) = &item {
+			self.used_memory -= entry_size(key, &item.value);
+			self.removals += 1;
+		}
+		item
+	}
+
+	fn clear(&mut self) {
+		self.cache = IndexMap::new();
+		self.expirations = BTreeSet::new();
+		self.used_memory = 0;
+		self.removals += 1;
+	}
+}
+
+/// Exclusive access to one shard. Writes made through it are logged to the
+/// append-only file and checked against the memory and key limits.
+pub struct ShardWriteGuard<'a> {
+	cache: &'a Cache,
+	index: usize,
+	shard: RwLockWriteGuard<'a, Shard>,
+	/// Memory and keys of the shard when the lock was taken, the totals of
+	/// the cache are adjusted by the difference once it is released.
+	used_memory: u64,
+	keys: usize,
+}
+
+impl<'a> ShardWriteGuard<'a> {
+	fn new(cache: &'a Cache, index: usize, shard: RwLockWriteGuard<'a, Shard>) -> Self {
+		ShardWriteGuard {
+			cache,
+			index,
+			used_memory: shard.used_memory,
+			keys: shard.cache.len(),
+			shard,
+		}
+	}
+
+	/// Stores `value` under `key` for `ttl` milliseconds, or forever when `ttl` is 0.
+	/// Fails when the memory or key limit is reached and nothing can be evicted.
+	/// Returns the version of the new value.
+	pub fn set(
+		&mut self,
+		key: String,
+		value: serde_json::Value,
+		ttl: u128,
+	) -> Result<u64, ErrorCode> {
+		self.set_as(key, value, ttl, Event::Set)
+	}
+
+	/// Like `set`, announcing the change as `event`.
+	pub fn set_as(
+		&mut self,
+		key: String,
+		value: serde_json::Value,
+		ttl: u128,
+		event: Event,
+	) -> Result<u64, ErrorCode> {
+		let data: Data = Data::Value(value);
+		self.make_room(&key, entry_size(&key, &data))?;
+		Ok(self.store(key, data, ttl, event))
+	}
+
+	/// Changes when `key` expires, `NO_EXPIRATION` makes it persistent and a
+	/// time in the past deletes it. Returns `false` when the key does not exist.
+	pub fn set_expiration(&mut self, key: &str, expiration: u128) -> bool {
+		if self.shard.live(key).is_none() {
+			return false;
+		}
+
+		if expired(expiration, current_time()) {
+			self.remove_as(key, Event::Expired);
+			return true;
+		}
+
+		self.shard.stats.writes.fetch_add(1, Ordering::Relaxed);
+		self.cache.log(|| AofEntry::Expire {
+			k: key.to_string(),
+			e: expiration,
+		});
+		self.shard.set_expiration(key, expiration);
+		if let Some(item) = self.shard.cache.get_mut(key) {
+			item.version = self.cache.versions.fetch_add(1, Ordering::Relaxed);
+		}
+		self.cache.emit(Event::Expire, key);
+		true
+	}
+
+	pub fn delete(&mut self, key: &str) {
+		self.remove_as(key, Event::Del);
+	}
+
+	/// Like `delete`, announcing the removal as `event`.
+	fn remove_as(&mut self, key: &str, event: Event) {
+		self.shard.stats.deletes.fetch_add(1, Ordering::Relaxed);
+		self.cache.log(|| AofEntry::Del { k: key.to_string() });
+		if self.shard.cache.contains_key(key) {
+			self.cache.emit(event, key);
+		}
+		self.shard.remove(key);
+	}
+
+	/// Runs `f` on the collection stored under `key`. A missing key is created
+	/// empty when `create` is set, otherwise `f` is skipped and `None` returned.
+	///
+	/// Fails with `WrongType` when the key holds something else. `f` must not
+	/// change anything when it fails, and a collection left empty is removed.
+	/// Logging the change is up to the caller.
+	pub fn modify<C: Collection, T>(
+		&mut self,
+		key: &str,
+		create: bool,
+		f: impl FnOnce(&mut C) -> Result<T, ErrorCode>,
+	) -> Result<Option<T>, ErrorCode> {
+		let size: u64 = match self.shard.live(key) {
+			Some(item) if C::from_data(&item.value).is_none() => return Err(ErrorCode::WrongType),
+			Some(item) => entry_size(key, &item.value),
+			None if create => entry_size(key, &C::default().into()),
+			None => return Ok(None),
+		};
+		// Like Redis, only check that the collection fits as it is now, a single
+		// command may then grow it past the limit.
+		if create {
+			self.make_room(key, size)?;
+		}
+
+		let result: T = self.change_collection(key, f)?;
+		self.shard.stats.writes.fetch_add(1, Ordering::Relaxed);
+		if self.shard.cache.contains_key(key) {
+			self.cache.emit(Event::Update, key);
+		}
+		Ok(Some(result))
+	}
+
+	/// Runs `f` on the collection under `key`, replacing whatever else the key holds
+	/// with an empty one, and keeps the memory usage and expiration index up to date.
+	fn change_collection<C: Collection, T>(
+		&mut self,
+		key: &str,
+		f: impl FnOnce(&mut C) -> Result<T, ErrorCode>,
+	) -> Result<T, ErrorCode> {
+		let reusable: bool = self
+			.shard
+			.live(key)
+			.is_some_and(|item| C::from_data(&item.value).is_some());
+		if !reusable {
+			self.shard.remove(key);
+			self.insert(
+				key.to_string(),
+				CacheItem::new(C::default().into(), NO_EXPIRATION),
+			);
+		}
+
+		let shard: &mut Shard = &mut self.shard;
+		let item: &mut CacheItem = shard.cache.get_mut(key).unwrap();
+		let old_size: u64 = entry_size(key, &item.value);
+		let result: Result<T, ErrorCode> = f(C::from_data_mut(&mut item.value).unwrap());
+		if result.is_ok() {
+			item.version = self.cache.versions.fetch_add(1, Ordering::Relaxed);
+		}
+		let new_size: u64 = entry_size(key, &item.value);
+		let empty: bool = item.value.is_empty();
+
+		shard.used_memory = shard.used_memory - old_size + new_size;
+		if empty {
+			shard.remove(key);
+			// A collection created by this call and left empty never existed.
+			if result.is_ok() && reusable {
+				self.cache.emit(Event::Del, key);
+			}
+		}
+		result
+	}
+
+	/// The part of `set` after making room, logs and inserts `data`.
+	fn store(&mut self, key: String, data: Data, ttl: u128, event: Event) -> u64 {
+		self.shard.stats.writes.fetch_add(1, Ordering::Relaxed);
+		let expiration: u128 = match ttl {
+			0 => NO_EXPIRATION,
+			ttl => current_time() + ttl,
+		};
+		self.cache.log(|| AofEntry::Set {
+			k: key.clone(),
+			v: data.to_json(),
+			e: expiration,
+		});
+		self.cache.emit(event, &key);
+		self.insert(key, CacheItem::new(data, expiration))
+	}
+
+	pub(super) fn log(&self, entry: impl FnOnce() -> AofEntry) {
+		self.cache.log(entry);
+	}
+
+	pub(super) fn notify_pushed(&self) {
+		self.cache.pushed.notify_waiters();
+	}
+
+	/// Evicts keys according to the eviction policy until an entry of
+	/// `new_size` bytes under `key` fits into the limits.
+	fn make_room(&mut self, key: &str, new_size: u64) -> Result<(), ErrorCode> {
+		let limits: Limits = self.cache.limits;
+		if limits.max_memory == 0 && limits.max_keys == 0 {
+			return Ok(());
+		}
+
+		let mut purged: bool = false;
+		loop {
+			let old_size: Option<u64> = self
+				.shard
+				.cache
+				.get(key)
+				.map(|item| entry_size(key, &item.value));
+			let (used_memory, keys) = self.usage();
+			let used_memory: u64 = used_memory.saturating_sub(old_size.unwrap_or(0)) + new_size;
+			let keys: usize = keys + old_size.is_none() as usize;
+			if !limits.exceeded(used_memory, keys) {
+				return Ok(());
+			}
+
+			// Expired keys go first, they are of no use to anyone.
+			if !purged {
+				purged = true;
+				if self.expire(usize::MAX) > 0 {
+					continue;
+				}
+			}
+
+			if let Some(victim) = self.shard.eviction_victim(limits.policy, &[key]) {
+				self.evict(&victim);
+				continue;
+			}
+			// Nothing left to evict here, take a key from a shard that is not busy.
+			if !self.cache.evict_elsewhere(&[self.index], &[key]) {
+				return Err(ErrorCode::OutOfMemory);
+			}
+		}
+	}
+
+	/// Like `Shard::expire`, announcing the removed keys, returns how many there were.
+	fn expire(&mut self, limit: usize) -> usize {
+		let expired: Vec<String> = self.shard.expire(limit);
+		for key in &expired {
+			self.cache.emit(Event::Expired, key);
+		}
+		expired.len()
+	}
+
+	/// Evicts the key the eviction policy picks, if any, and tells whether
+	/// there was one.
+	fn evict_one(&mut self, exclude: &[&str]) -> bool {
+		match self
+			.shard
+			.eviction_victim(self.cache.limits.policy, exclude)
+		{
+			Some(victim) => {
+				self.evict(&victim);
+				true
+			}
+			None => false,
+		}
+	}
+
+	fn evict(&mut self, key: &str) {
+		self.cache.log(|| AofEntry::Del { k: key.to_string() });
+		self.cache.emit(Event::Evicted, key);
+		self.shard.remove(key);
+		self.shard.stats.evicted.fetch_add(1, Ordering::Relaxed);
+	}
+
+	fn insert(&mut self, key: String, mut item: CacheItem) -> u64 {
+		item.sequence = self.cache.sequence.fetch_add(1, Ordering::Relaxed);
+		item.version = self.cache.versions.fetch_add(1, Ordering::Relaxed);
+		let version: u64 = item.version;
+		self.shard.insert(key, item);
+		version
+	}
+
+	/// Memory and keys of the whole cache, including the changes made
+	/// through this guard.
+	fn usage(&self) -> (u64, usize) {
+		(
+			(self.cache.used_memory.load(Ordering::Relaxed) + self.shard.used_memory)
+				.saturating_sub(self.used_memory),
+			(self.cache.keys.load(Ordering::Relaxed) + self.shard.cache.len()).saturating_sub(self.keys),
+		)
+	}
+}
+
+impl Deref for ShardWriteGuard<'_> {
+	type Target = Shard;
+
+	fn deref(&self) -> &Shard {
+		&self.shard
+	}
+}
+
+impl Drop for ShardWriteGuard<'_> {
+	fn drop(&mut self) {
+		// Add before subtracting, so the totals never drop below zero in between.
+		self
+			.cache
+			.used_memory
+			.fetch_add(self.shard.used_memory, Ordering::Relaxed);
+		self
+			.cache
+			.used_memory
+			.fetch_sub(self.used_memory, Ordering::Relaxed);
+		self
+			.cache
+			.keys
+			.fetch_add(self.shard.cache.len(), Ordering::Relaxed);
+		self.cache.keys.fetch_sub(self.keys, Ordering::Relaxed);
+	}
+}
+
+/// Shards locked together by `Cache::read_many`, in index order like `write_all`.
+pub struct ShardsReadGuard<'a> {
+	cache: &'a Cache,
+	shards: Vec<(usize, RwLockReadGuard<'a, Shard>)>,
+}
+
+impl ShardsReadGuard<'_> {
+	/// The shard holding `key`, which must be one of the keys the guard was taken for.
+	pub fn shard(&self, key: &str) -> &Shard {
+		let index: usize = self.cache.index(key);
+		let position: usize = self
+			.shards
+			.binary_search_by_key(&index, |(index, _)| *index)
+			.expect("key not locked by this guard");
+		&self.shards[position].1
+	}
+}
+
+/// Shards locked together by `Cache::write_many`, in index order like `write_all`.
+pub struct ShardsWriteGuard<'a> {
+	cache: &'a Cache,
+	shards: Vec<ShardWriteGuard<'a>>,
+}
+
+impl<'a> ShardsWriteGuard<'a> {
+	/// The shard holding `key`, which must be one of the keys the guard was taken for.
+	pub fn shard(&mut self, key: &str) -> &mut ShardWriteGuard<'a> {
+		let position: usize = self.position(key);
+		&mut self.shards[position]
+	}
+
+	/// Stores all `items`, given as key, value and ttl in milliseconds, or none
+	/// of them when they do not fit into the limits together. Returns the
+	/// versions of the new values in order.
+	pub fn set_all(
+		&mut self,
+		items: Vec<(String, serde_json::Value, u128)>,
+	) -> Result<Vec<u64>, ErrorCode> {
+		let items: Vec<(String, Data, u128)> = items
+			.into_iter()
+			.map(|(key, value, ttl)| (key, Data::Value(value), ttl))
+			.collect();
+		// A key given more than once ends up with its last value, only that one needs room.
+		let mut seen: HashSet<&str> = HashSet::new();
+		let sizes: Vec<(&str, u64)> = items
+			.iter()
+			.rev()
+			.filter(|(key, _, _)| seen.insert(key.as_str()))
+			.map(|(key, data, _)| (key.as_str(), entry_size(key, data)))
+			.collect();
+		self.make_room(&sizes)?;
+
+		Ok(
+			items
+				.into_iter()
+				.map(|(key, data, ttl)| self.shard(&key).store(key, data, ttl, Event::Set))
+				.collect(),
+		)
+	}
+
+	/// Like `ShardWriteGuard::make_room`, for several entries at once.
+	/// Victims are taken from the locked shards first.
+	fn make_room(&mut self, entries: &[(&str, u64)]) -> Result<(), ErrorCode> {
+		let limits: Limits = self.cache.limits;
+		if (limits.max_memory == 0 && limits.max_keys == 0) || self.shards.is_empty() {
+			return Ok(());
+		}
+
+		let exclude: Vec<&str> = entries.iter().map(|(key, _)| *key).collect();
+		let mut purged: bool = false;
+		loop {
+			let (mut used_memory, mut keys) = self.usage();
+			for (key, new_size) in entries {
+				let old_size: Option<u64> = self.shards[self.position(key)]
+					.shard
+					.cache
+					.get(*key)
+					.map(|item| entry_size(key, &item.value));
+				used_memory = used_memory.saturating_sub(old_size.unwrap_or(0)) + new_size;
+				keys += old_size.is_none() as usize;
+			}
+			if !limits.exceeded(used_memory, keys) {
+				return Ok(());
+			}
+
+			if !purged {
+				purged = true;
+				let expired: usize = self
+					.shards
+					.iter_mut()
+					.map(|shard| shard.expire(usize::MAX))
+					.sum();
+				if expired > 0 {
+					continue;
+				}
+			}
+
+			let victim: Option<(&mut ShardWriteGuard, String)> =
+				self.shards.iter_mut().find_map(|shard| {
+					let victim: String = shard.shard.eviction_victim(limits.policy, &exclude)?;
+					Some((shard, victim))
+				});
+			if let Some((shard, victim)) = victim {
+				shard.evict(&victim);
+				continue;
+			}
+			let held: Vec<usize> = self.shards.iter().map(|shard| shard.index).collect();
+			if !self.cache.evict_elsewhere(&held, &exclude) {
+				return Err(ErrorCode::OutOfMemory);
+			}
+		}
+	}
+
+	/// Memory and keys of the whole cache, including the changes made
+	/// through this guard.
+	fn usage(&self) -> (u64, usize) {
+		let mut used_memory: u64 = self.cache.used_memory.load(Ordering::Relaxed);
+		let mut keys: usize = self.cache.keys.load(Ordering::Relaxed);
+		for shard in &self.shards {
+			used_memory = (used_memory + shard.shard.used_memory).saturating_sub(shard.used_memory);
+			keys = (keys + shard.shard.cache.len()).saturating_sub(shard.keys);
+		}
+		(used_memory, keys)
+	}
+
+	fn position(&self, key: &str) -> usize {
+		let index: usize = self.cache.index(key);
+		self
+			.shards
+			.binary_search_by_key(&index, |shard| shard.index)
+			.expect("key not locked by this guard")
+	}
+}
+
+/// State of the snapshot and the append-only log, locked separately from the shards.
+/// Code that needs both takes the shard locks first.
+#[derive(Default)]
+pub struct Persistence {
+	pub aof: Option<Aof>,
+	pub snapshot_size: u64,
+	pub last_save_time: u128,
+	pub last_save_duration: u128,
+	pub last_save_status: SaveStatus,
+	snapshot_generation: u64,
+	changes_at_save: u64,
+	changes_at_compaction: u64,
+}
+
+impl Persistence {
+	/// Records the outcome of a snapshot that was started at `started`.
+	pub fn record_save(&mut self, started: u128, success: bool) {
+		let finished: u128 = current_time();
+		self.last_save_duration = finished - started;
+		if success {
+			self.last_save_time = finished;
+			self.last_save_status = SaveStatus::Ok;
+		} else {
+			self.last_save_status = SaveStatus::Failed;
+		}
+	}
+}
+
+/// The keyspace split into shards by key hash, each behind its own lock.
+///
+/// Operations on a single key only lock the shard of that key: reads take it
+/// in shared mode and writes in exclusive mode. Operations on the whole cache
+/// lock the shards one after another, always in index order.
+pub struct Cache {
+	shards: Box<[RwLock<Shard>]>,
+	hasher: RandomState,
 	pub path: String,
 	pub preserve_order: bool,
+	pub snapshot_options: SnapshotOptions,
+	pub limits: Limits,
+	used_memory: AtomicU64,
+	keys: AtomicUsize,
+	lists: AtomicU64,
+	sequence: AtomicU64,
+	/// Source of item versions. It starts from the time the cache was created, so
+	/// versions handed out before a restart are never repeated after it, and it
+	/// leaves room for about a million writes per second in between. Versions are
+	/// not persisted, loaded items get new ones.
+	versions: AtomicU64,
+	persistence: Mutex<Persistence>,
+	/// Woken whenever elements are pushed to a list, see `pushed`.
+	pushed: Notify,
+	events: broadcast::Sender<KeyEvent>,
+	/// One per shard, taken in shared mode by operations on keys of the shard
+	/// and in exclusive mode by transactions on them, so nothing can see or
+	/// change those keys halfway through a transaction, see `SharedState`.
+	transactions: Box<[tokio::sync::RwLock<()>]>,
 }
 
 impl Cache {
-	pub fn new(path: String, preserve_order: bool) -> Self {
+	pub fn new(
+		path: String,
+		preserve_order: bool,
+		snapshot_options: SnapshotOptions,
+		limits: Limits,
+		shards: usize,
+	) -> Self {
 		Cache {
-			cache: IndexMap::new(),
-			stats: Stats::default(),
+			shards: (0..shards.max(1))
+				.map(|_| {
+					RwLock::new(Shard {
+						preserve_order,
+						..Shard::default()
+					})
+				})
+				.collect(),
+			hasher: RandomState::new(),
 			path,
 			preserve_order,
+			snapshot_options,
+			limits,
+			used_memory: AtomicU64::new(0),
+			keys: AtomicUsize::new(0),
+			lists: AtomicU64::new(0),
+			sequence: AtomicU64::new(0),
+			versions: AtomicU64::new((current_time() as u64 / 1000) << 20),
+			persistence: Mutex::new(Persistence::default()),
+			pushed: Notify::new(),
+			events: broadcast::channel(EVENT_BUFFER).0,
+			transactions: (0..shards.max(1))
+				.map(|_| tokio::sync::RwLock::new(()))
+				.collect(),
 		}
 	}
 
-	pub fn set(&mut self, key: String, value: serde_json::Value, ttl: u128) {
-		self.stats.writes += 1;
-		let expiration: u128 = current_time() + ttl;
-		self.cache.insert(key, CacheItem { expiration, value });
+	/// Shared access to the shard holding `key`, readers do not block each other.
+	pub fn read(&self, key: &str) -> RwLockReadGuard<'_, Shard> {
+		self.shards[self.index(key)].read().unwrap()
 	}
 
-	pub fn get(&mut self, key: &str) -> Option<&CacheItem> {
-		self.stats.reads += 1;
-		self
-			.cache
-			.get(key)
-			.filter(|&item| item.expiration > current_time())
+	/// Shared access to the shards holding `keys` at once, for reads that
+	/// need a consistent view of several keys.
+	pub fn read_many(&self, keys: &[String]) -> ShardsReadGuard<'_> {
+		let mut indices: Vec<usize> = keys.iter().map(|key| self.index(key)).collect();
+		indices.sort_unstable();
+		indices.dedup();
+		ShardsReadGuard {
+			cache: self,
+			shards: indices
+				.into_iter()
+				.map(|index| (index, self.shards[index].read().unwrap()))
+				.collect(),
+		}
 	}
 
-	pub fn delete(&mut self, key: &str) {
-		self.stats.deletes += 1;
+	/// Exclusive access to the shard holding `key`.
+	pub fn write(&self, key: &str) -> ShardWriteGuard<'_> {
+		self.write_shard(self.index(key))
+	}
+
+	/// Exclusive access to the shards holding `keys` at once, for writes that
+	/// must be seen all together or not at all.
+	pub fn write_many(&self, keys: &[String]) -> ShardsWriteGuard<'_> {
+		let mut indices: Vec<usize> = keys.iter().map(|key| self.index(key)).collect();
+		indices.sort_unstable();
+		indices.dedup();
+		ShardsWriteGuard {
+			cache: self,
+			shards: indices
+				.into_iter()
+				.map(|index| self.write_shard(index))
+				.collect(),
+		}
+	}
+
+	pub fn persistence(&self) -> MutexGuard<'_, Persistence> {
+		self.persistence.lock().unwrap()
+	}
+
+	/// Completes on the next push to any list. Create it before checking the
+	/// lists, so a push made right after the check is not missed.
+	pub fn pushed(&self) -> Notified<'_> {
+		self.pushed.notified()
+	}
+
+	/// Changes made to keys from now on. Events are only built while someone
+	/// listens, and a listener that falls behind misses some.
+	pub fn events(&self) -> broadcast::Receiver<KeyEvent> {
+		self.events.subscribe()
+	}
+
+	pub fn shard_count(&self) -> usize {
+		self.shards.len()
+	}
+
+	/// Transaction lock of shard `index`, see `SharedState::operation`.
+	pub fn transaction_lock(&self, index: usize) -> &tokio::sync::RwLock<()> {
+		&self.transactions[index]
+	}
+
+	pub fn key_count(&self) -> usize {
+		self.keys.load(Ordering::Relaxed)
+	}
+
+	/// Estimated memory used by all entries, see `eviction::entry_size`.
+	pub fn used_memory(&self) -> u64 {
+		self.used_memory.load(Ordering::Relaxed)
+	}
+
+	/// Keys starting with `prefix`, skipping `cursor` of them. Expired keys are left
+	/// out but not removed, so only read locks are taken, see `tasks::active_expire`.
+	pub fn list(&self, limit: usize, cursor: usize, prefix: &str) -> Vec<String> {
+		self.lists.fetch_add(1, Ordering::Relaxed);
+		let current_time: u128 = current_time();
+		let listed = |(key, item): &(&String, &CacheItem)| {
+			key.starts_with(prefix) && !item.is_expired(current_time)
+		};
+
 		if self.preserve_order {
-			self.cache.shift_remove(key);
-		} else {
-			self.cache.swap_remove(key);
+			let shards: Vec<RwLockReadGuard<Shard>> = self.read_all();
+			let mut keys: Vec<(u64, &String)> = shards
+				.iter()
+				.flat_map(|shard| shard.cache.iter())
+				.filter(listed)
+				.map(|(key, item)| (item.sequence, key))
+				.collect();
+			keys.sort_unstable();
+			return keys
+				.into_iter()
+				.skip(cursor)
+				.take(limit)
+				.map(|(_, key)| key.clone())
+				.collect();
+		}
+
+		let mut keys: Vec<String> = Vec::new();
+		let mut skip: usize = cursor;
+		for lock in self.shards.iter() {
+			let shard: RwLockReadGuard<Shard> = lock.read().unwrap();
+			for (key, _) in shard.cache.iter().filter(listed) {
+				if keys.len() == limit {
+					return keys;
+				}
+				match skip {
+					0 => keys.push(key.clone()),
+					_ => skip -= 1,
+				}
+			}
 		}
+		keys
 	}
 
-	pub fn list(&mut self, limit: usize, cursor: usize, prefix: &str) -> Vec<&String> {
-		self.stats.lists += 1;
-		self
-			.cache
-			.keys()
-			.filter(move |k: &&String| k.starts_with(prefix))
-			.enumerate()
-			.skip(cursor)
-			.take(limit)
-			.map(|(_i, k)| k)
-			.collect()
+	pub fn clean(&self) {
+		for index in 0..self.shards.len() {
+			self.expire_shard(index, usize::MAX);
+		}
 	}
 
-	pub fn clean(&mut self) {
+	/// Removes up to `limit` expired keys from one shard, earliest expiration
+	/// first, and returns how many were removed.
+	pub fn expire_shard(&self, index: usize, limit: usize) -> usize {
+		self.write_shard(index).expire(limit)
+	}
+
+	/// Keys that expire within `within` milliseconds, soonest first,
+	/// with their remaining time to live in milliseconds. Expired keys are left
+	/// out but not removed, like in `list`.
+	pub fn expiring(&self, within: u128, limit: usize) -> Vec<(String, u128)> {
 		let current_time: u128 = current_time();
-		self
-			.cache
-			.retain(|_, cache_item| current_time < cache_item.expiration)
+		let mut keys: Vec<(String, u128)> = Vec::new();
+		for lock in self.shards.iter() {
+			let shard: RwLockReadGuard<Shard> = lock.read().unwrap();
+			keys.extend(
+				shard
+					.expirations
+					.iter()
+					.skip_while(|(expiration, _)| expired(*expiration, current_time))
+					.take_while(|(expiration, _)| *expiration <= current_time + within)
+					.take(limit)
+					.map(|(expiration, key)| (key.clone(), expiration.saturating_sub(current_time))),
+			);
+		}
+		keys.sort_by_key(|(_, ttl)| *ttl);
+		keys.truncate(limit);
+		keys
 	}
 
-	pub fn flush(&mut self) {
-		self.cache = IndexMap::new()
+	pub fn flush(&self) {
+		let mut shards: Vec<ShardWriteGuard> = self.write_all();
+		self.log(|| AofEntry::Flush);
+		for shard in shards.iter_mut() {
+			shard.shard.clear();
+		}
+		self.emit(Event::Flush, "");
 	}
 
-	pub fn load(&mut self) -> io::Result<()> {
-		match read_cache_from_file(&self.path) {
-			Ok(cache_map) => {
-				let cur_time: u128 = current_time();
-				for (key, value) in cache_map {
-					if value.e < cur_time {
-						continue;
-					}
-					let cache_item = CacheItem {
-						expiration: value.e,
-						value: value.v,
-					};
-					self.cache.insert(key, cache_item);
+	pub fn stats(&self) -> Stats {
+		let mut stats: Stats = Stats {
+			lists: self.lists.load(Ordering::Relaxed),
+			..Stats::default()
+		};
+		for lock in self.shards.iter() {
+			let shard: RwLockReadGuard<Shard> = lock.read().unwrap();
+			let shard_stats: ShardStats =
+				ShardStats::new(shard.cache.len(), shard.used_memory, &shard.stats);
+			stats.writes += shard_stats.writes;
+			stats.reads += shard_stats.reads;
+			stats.deletes += shard_stats.deletes;
+			stats.expired += shard_stats.expired;
+			stats.evicted += shard_stats.evicted;
+			stats.shards.push(shard_stats);
+		}
+
+		let persistence: MutexGuard<Persistence> = self.persistence();
+		stats.last_save_time = persistence.last_save_time;
+		stats.last_save_duration = persistence.last_save_duration;
+		stats.last_save_status = persistence.last_save_status;
+		stats
+	}
+
+	/// Loads the snapshot and replays the append-only log. When the snapshot is
+	/// corrupt, the previous one is loaded instead and the log of the writes made
+	/// between the two is replayed first, see `write_snapshot`.
+	pub fn load(&self) -> io::Result<()> {
+		let snapshot: Option<(Items, u64)> = match snapshot::read(&self.path) {
+			Ok(snapshot) => snapshot,
+			Err(err) => {
+				eprintln!("Snapshot in {} is unreadable: {}", self.path, err);
+				Some(self.restore_backup().map_err(|backup_err| {
+					eprintln!("Previous snapshot cannot be loaded either: {}", backup_err);
+					err
+				})?)
+			}
+		};
+		if let Some((cache_map, size)) = snapshot {
+			self.persistence().snapshot_size = size;
+			let cur_time: u128 = current_time();
+			for (key, value) in cache_map {
+				if expired(value.e, cur_time) {
+					continue;
 				}
-				Ok(())
+				let data: Data = Data::from_json(value.t, value.v).ok_or_else(|| {
+					io::Error::new(
+						io::ErrorKind::InvalidData,
+						format!("Snapshot entry {} does not match its type", key),
+					)
+				})?;
+				self.write(&key).insert(key, CacheItem::new(data, value.e));
 			}
-			Err(err) => Err(err),
 		}
+
+		// Entries of an interrupted compaction come before the current log.
+		let cur_time: u128 = current_time();
+		for path in [self.old_aof_path(), self.aof_path()] {
+			aof::replay(&path, |entry| self.apply(entry, cur_time))?;
+		}
+		Ok(())
 	}
 
+	/// Writes a snapshot while writers are held off, readers carry on meanwhile.
 	pub fn save(&self) -> io::Result<()> {
-		let mut cache_map: HashMap<String, CacheItemSmall> = HashMap::new();
-		let cur_time: u128 = current_time();
-		for (key, value) in &self.cache {
-			if value.expiration < cur_time {
+		let started: u128 = current_time();
+		let shards: Vec<RwLockReadGuard<Shard>> = self.read_all();
+		let mut persistence: MutexGuard<Persistence> = self.persistence();
+		let result: io::Result<()> = self.write_snapshot(&shards, &mut persistence);
+		if result.is_ok() {
+			persistence.changes_at_save = changes(&shards);
+		}
+		persistence.record_save(started, result.is_ok());
+		result
+	}
+
+	fn write_snapshot(
+		&self,
+		shards: &[RwLockReadGuard<Shard>],
+		persistence: &mut Persistence,
+	) -> io::Result<()> {
+		let items: Items = self.snapshot_items(shards);
+		// The current snapshot becomes the backup, the log kept for the old one is stale.
+		remove_if_exists(&self.backup_aof_path())?;
+		persistence.snapshot_size = snapshot::write(&self.path, &items, self.snapshot_options)?;
+		persistence.snapshot_generation += 1;
+
+		// Everything logged so far is now part of the snapshot, and kept as what
+		// the backup lacks. Writes that were not logged cannot be kept.
+		match &mut persistence.aof {
+			Some(aof) => {
+				aof.rotate(&self.aof_path(), &self.old_aof_path())?;
+				fs::rename(self.old_aof_path(), self.backup_aof_path())
+			}
+			None => {
+				remove_if_exists(&self.old_aof_path())?;
+				remove_if_exists(&self.aof_path())
+			}
+		}
+	}
+
+	/// Makes the previous snapshot the current one and returns its items. Its log
+	/// goes in front of the one of the corrupt snapshot, which is replayed next.
+	fn restore_backup(&self) -> io::Result<(Items, u64)> {
+		let snapshot: (Items, u64) = snapshot::read_backup(&self.path)?;
+		let (backup_log, old_log) = (self.backup_aof_path(), self.old_aof_path());
+		if !backup_log.exists() {
+			eprintln!("No log of the writes made after the previous snapshot, they are lost");
+		} else if old_log.exists() {
+			let joined: PathBuf = PathBuf::from(format!("{}/appendonly.aof.joined", self.path));
+			let mut file: File = File::create(&joined)?;
+			io::copy(&mut File::open(&backup_log)?, &mut file)?;
+			io::copy(&mut File::open(&old_log)?, &mut file)?;
+			file.sync_all()?;
+			fs::rename(&joined, &old_log)?;
+			fs::remove_file(&backup_log)?;
+		} else {
+			fs::rename(&backup_log, &old_log)?;
+		}
+		snapshot::restore_backup(&self.path)?;
+		Ok(snapshot)
+	}
+
+	/// First step of a background compaction, done while writers are held off.
+	///
+	/// Moves the append-only log aside and returns a copy of the live items
+	/// together with a generation number for `finish_compaction`.
+	pub fn begin_compaction(&self) -> io::Result<(u64, Items)> {
+		let shards: Vec<RwLockReadGuard<Shard>> = self.read_all();
+		let mut persistence: MutexGuard<Persistence> = self.persistence();
+		if let Some(aof) = &mut persistence.aof {
+			aof.rotate(&self.aof_path(), &self.old_aof_path())?;
+		}
+		persistence.snapshot_generation += 1;
+		persistence.changes_at_compaction = changes(&shards);
+		Ok((
+			persistence.snapshot_generation,
+			self.snapshot_items(&shards),
+		))
+	}
+
+	/// Last step of a background compaction, once the copy from
+	/// `begin_compaction` was written to `compaction_path`.
+	///
+	/// Returns `false` when a newer snapshot was saved in the meantime, in which
+	/// case the compacted one is discarded.
+	pub fn finish_compaction(&self, generation: u64, size: u64) -> io::Result<bool> {
+		let compacted: PathBuf = self.compaction_path();
+		let mut persistence: MutexGuard<Persistence> = self.persistence();
+		if generation != persistence.snapshot_generation {
+			remove_if_exists(&compacted)?;
+			return Ok(false);
+		}
+		remove_if_exists(&self.backup_aof_path())?;
+		snapshot::install(&self.path, &compacted)?;
+		persistence.snapshot_size = size;
+		persistence.changes_at_save = persistence.changes_at_compaction;
+		// The compacted log is what the replaced snapshot, now the backup, lacks.
+		if self.old_aof_path().exists() {
+			fs::rename(self.old_aof_path(), self.backup_aof_path())?;
+		}
+		Ok(true)
+	}
+
+	/// Records the outcome of a snapshot that was started at `started`.
+	pub fn record_save(&self, started: u128, success: bool) {
+		self.persistence().record_save(started, success);
+	}
+
+	/// Number of writes and deletes not yet covered by a snapshot.
+	pub fn changes_since_save(&self) -> u64 {
+		let changes: u64 = changes(&self.read_all());
+		changes.saturating_sub(self.persistence().changes_at_save)
+	}
+
+	/// Whether the append-only log has outgrown the snapshot enough to be compacted.
+	pub fn needs_compaction(&self, percentage: u64, min_size: u64) -> bool {
+		let persistence: MutexGuard<Persistence> = self.persistence();
+		match &persistence.aof {
+			Some(aof) => {
+				aof.size >= min_size
+					&& aof.size * 100 >= persistence.snapshot_size.saturating_mul(percentage)
+			}
+			None => false,
+		}
+	}
+
+	/// Starts recording every write to the append-only log.
+	/// Must be called after `load`, so the existing log is replayed first.
+	pub fn open_aof(&self, policy: FsyncPolicy) -> io::Result<()> {
+		self.persistence().aof = Some(Aof::open(&self.aof_path(), policy)?);
+		Ok(())
+	}
+
+	pub fn compaction_path(&self) -> PathBuf {
+		PathBuf::from(format!("{}/cache.json.compact", self.path))
+	}
+
+	fn aof_path(&self) -> PathBuf {
+		PathBuf::from(format!("{}/appendonly.aof", self.path))
+	}
+
+	fn old_aof_path(&self) -> PathBuf {
+		PathBuf::from(format!("{}/appendonly.aof.old", self.path))
+	}
+
+	/// Log of the writes made between the backup snapshot and the current one.
+	fn backup_aof_path(&self) -> PathBuf {
+		PathBuf::from(format!("{}/appendonly.aof.bak", self.path))
+	}
+
+	/// Index of the shard holding `key`.
+	pub fn index(&self, key: &str) -> usize {
+		(self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+	}
+
+	fn write_shard(&self, index: usize) -> ShardWriteGuard<'_> {
+		ShardWriteGuard::new(self, index, self.shards[index].write().unwrap())
+	}
+
+	fn read_all(&self) -> Vec<RwLockReadGuard<'_, Shard>> {
+		self
+			.shards
+			.iter()
+			.map(|lock| lock.read().unwrap())
+			.collect()
+	}
+
+	fn write_all(&self) -> Vec<ShardWriteGuard<'_>> {
+		(0..self.shards.len())
+			.map(|index| self.write_shard(index))
+			.collect()
+	}
+
+	/// Evicts one key from a shard other than the `held` ones, which the caller
+	/// has locked. Free shards are tried first. If none of them has a key to
+	/// spare, the busy shards after the held ones are waited for, which keeps to
+	/// the index order shard locks are taken in. Shards with a running
+	/// transaction are skipped, their keys must not change under it.
+	fn evict_elsewhere(&self, held: &[usize], exclude: &[&str]) -> bool {
+		let mut busy: Vec<usize> = Vec::new();
+		for other in (0..self.shards.len()).filter(|other| !held.contains(other)) {
+			let Ok(_transaction) = self.transactions[other].try_read() else {
 				continue;
+			};
+			match self.shards[other].try_write() {
+				Ok(shard) => {
+					if ShardWriteGuard::new(self, other, shard).evict_one(exclude) {
+						return true;
+					}
+				}
+				Err(_) => busy.push(other),
+			}
+		}
+
+		let last: usize = held.iter().copied().max().unwrap_or(0);
+		for other in busy.into_iter().filter(|other| *other > last) {
+			let Ok(_transaction) = self.transactions[other].try_read() else {
+				continue;
+			};
+			let shard: RwLockWriteGuard<Shard> = self.shards[other].write().unwrap();
+			if ShardWriteGuard::new(self, other, shard).evict_one(exclude) {
+				return true;
+			}
+		}
+		false
+	}
+
+	fn snapshot_items(&self, shards: &[RwLockReadGuard<Shard>]) -> Items {
+		let current_time: u128 = current_time();
+		let mut items: Vec<(&String, &CacheItem)> = shards
+			.iter()
+			.flat_map(|shard| shard.cache.iter())
+			.filter(|(_, item)| !item.is_expired(current_time))
+			.collect();
+		if self.preserve_order {
+			items.sort_unstable_by_key(|(_, item)| item.sequence);
+		}
+
+		items
+			.into_iter()
+			.map(|(key, item)| {
+				(
+					key.clone(),
+					CacheItemSmall {
+						v: item.value.to_json(),
+						e: item.expiration,
+						t: item.value.data_type(),
+					},
+				)
+			})
+			.collect()
+	}
+
+	fn apply(&self, entry: AofEntry, cur_time: u128) {
+		match entry {
+			AofEntry::Set { k, v, e } => {
+				let mut shard: ShardWriteGuard = self.write(&k);
+				if expired(e, cur_time) {
+					shard.shard.remove(&k);
+				} else {
+					shard.insert(k, CacheItem::new(Data::Value(v), e));
+				}
+			}
+			AofEntry::Expire { k, e } => {
+				let mut shard: ShardWriteGuard = self.write(&k);
+				if expired(e, cur_time) {
+					shard.shard.remove(&k);
+				} else {
+					shard.shard.set_expiration(&k, e);
+				}
+			}
+			AofEntry::Hset { k, f, v } => {
+				let _ = self.write(&k).change_collection(&k, |hash: &mut Hash| {
+					hash.insert(f, v);
+					Ok(())
+				});
+			}
+			AofEntry::Hdel { k, f } => {
+				let _ = self.write(&k).change_collection(&k, |hash: &mut Hash| {
+					hash.remove(&f);
+					Ok(())
+				});
+			}
+			AofEntry::Lpush { k, i, v } => {
+				let _ = self.write(&k).change_collection(&k, |list: &mut List| {
+					list.push_front(i, v);
+					Ok(())
+				});
+			}
+			AofEntry::Rpush { k, i, v } => {
+				let _ = self.write(&k).change_collection(&k, |list: &mut List| {
+					list.push_back(i, v);
+					Ok(())
+				});
+			}
+			AofEntry::Lpop { k, i } => {
+				let _ = self.write(&k).change_collection(&k, |list: &mut List| {
+					list.pop_front(i);
+					Ok(())
+				});
+			}
+			AofEntry::Rpop { k, i } => {
+				let _ = self.write(&k).change_collection(&k, |list: &mut List| {
+					list.pop_back(i);
+					Ok(())
+				});
+			}
+			AofEntry::Ltrim { k, f, l } => {
+				let _ = self.write(&k).change_collection(&k, |list: &mut List| {
+					list.retain(f, l);
+					Ok(())
+				});
+			}
+			AofEntry::Sadd { k, m } => {
+				let _ = self.write(&k).change_collection(&k, |set: &mut Set| {
+					set.insert(m);
+					Ok(())
+				});
+			}
+			AofEntry::Srem { k, m } => {
+				let _ = self.write(&k).change_collection(&k, |set: &mut Set| {
+					set.remove(&m);
+					Ok(())
+				});
+			}
+			AofEntry::Zadd { k, m, s } => {
+				let _ = self.write(&k).change_collection(&k, |set: &mut SortedSet| {
+					set.insert(m, s);
+					Ok(())
+				});
+			}
+			AofEntry::Zrem { k, m } => {
+				let _ = self.write(&k).change_collection(&k, |set: &mut SortedSet| {
+					set.remove(&m);
+					Ok(())
+				});
+			}
+			AofEntry::Del { k } => self.write(&k).shard.remove(&k),
+			AofEntry::Flush => {
+				for mut shard in self.write_all() {
+					shard.shard.clear();
+				}
+			}
+		}
+	}
+
+	fn emit(&self, event: Event, key: &str) {
+		if self.events.receiver_count() > 0 {
+			let event: KeyEvent = KeyEvent {
+				event,
+				key: key.to_string(),
+			};
+			self.events.send(event).ok();
+		}
+	}
+
+	fn log(&self, entry: impl FnOnce() -> AofEntry) {
+		if let Some(aof) = &mut self.persistence().aof {
+			if let Err(err) = aof.append(&entry()) {
+				eprintln!("Failed to write to append-only log: {}", err);
 			}
-			cache_map.insert(
-				key.clone(),
-				CacheItemSmall {
-					v: value.value.clone(),
-					e: value.expiration,
-				},
-			);
 		}
-		let json_str = serde_json::to_string_pretty(&cache_map).unwrap();
-		write_cache_to_file(&self.path, &json_str)
 	}
 }
 
-fn read_cache_from_file(path: &str) -> io::Result<HashMap<String, CacheItemSmall>> {
-	let json_str: String = fs::read_to_string(format!("{}/cache.json", path))?;
-	Ok(serde_json::from_str(&json_str)?)
+fn changes(shards: &[RwLockReadGuard<Shard>]) -> u64 {
+	shards.iter().map(|shard| shard.stats.changes()).sum()
 }
 
-fn write_cache_to_file(path: &str, json_str: &str) -> io::Result<()> {
-	fs::write(format!("{}/cache.json", path), json_str)?;
-	Ok(())
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+	match fs::remove_file(path) {
+		Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+		_ => Ok(()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn directory(name: &str) -> String {
+		let path: PathBuf =
+			std::env::temp_dir().join(format!("rabbit-kv-cache-{}-{}", std::process::id(), name));
+		fs::remove_dir_all(&path).ok();
+		fs::create_dir_all(&path).unwrap();
+		path.to_string_lossy().into_owned()
+	}
+
+	fn new_cache(path: &str, limits: Limits) -> Cache {
+		Cache::new(
+			path.to_string(),
+			false,
+			SnapshotOptions::default(),
+			limits,
+			4,
+		)
+	}
+
+	fn set(cache: &Cache, key: &str, value: i64) -> u64 {
+		cache
+			.write(key)
+			.set(key.to_string(), serde_json::json!(value), 0)
+			.unwrap()
+	}
+
+	fn value(cache: &Cache, key: &str) -> Option<serde_json::Value> {
+		match &cache.read(key).get(key)?.value {
+			Data::Value(value) => Some(value.clone()),
+			_ => None,
+		}
+	}
+
+	fn values(cache: &Cache, keys: &[&str]) -> Vec<Option<serde_json::Value>> {
+		keys.iter().map(|key| value(cache, key)).collect()
+	}
+
+	/// Loads the cache in `path` again, as a restart would.
+	fn reload(path: &str) -> Cache {
+		let cache: Cache = new_cache(path, Limits::default());
+		cache.load().unwrap();
+		cache
+	}
+
+	fn corrupt_snapshot(path: &str) {
+		fs::write(snapshot::snapshot_path(path), b"not a snapshot").unwrap();
+	}
+
+	fn keys(count: usize) -> Vec<String> {
+		(0..count).map(|i| format!("key-{}", i)).collect()
+	}
+
+	#[test]
+	fn falls_back_to_the_previous_snapshot_and_its_log() {
+		let path: String = directory("backup");
+		let cache: Cache = new_cache(&path, Limits::default());
+		cache.open_aof(FsyncPolicy::Always).unwrap();
+		set(&cache, "a", 1);
+		cache.save().unwrap();
+		set(&cache, "b", 2);
+		cache.save().unwrap();
+		set(&cache, "c", 3);
+		drop(cache);
+
+		corrupt_snapshot(&path);
+		let expected = vec![Some(1.into()), Some(2.into()), Some(3.into())];
+		assert_eq!(values(&reload(&path), &["a", "b", "c"]), expected);
+		// The files are consistent again after the recovery.
+		assert_eq!(values(&reload(&path), &["a", "b", "c"]), expected);
+	}
+
+	#[test]
+	fn falls_back_after_a_compaction() {
+		let path: String = directory("compaction");
+		let cache: Cache = new_cache(&path, Limits::default());
+		cache.open_aof(FsyncPolicy::Always).unwrap();
+		set(&cache, "a", 1);
+		cache.save().unwrap();
+		set(&cache, "b", 2);
+		let (generation, items) = cache.begin_compaction().unwrap();
+		let size: u64 =
+			snapshot::write_file(&cache.compaction_path(), &items, SnapshotOptions::default()).unwrap();
+		assert!(cache.finish_compaction(generation, size).unwrap());
+		set(&cache, "c", 3);
+		drop(cache);
+
+		corrupt_snapshot(&path);
+		assert_eq!(
+			values(&reload(&path), &["a", "b", "c"]),
+			vec![Some(1.into()), Some(2.into()), Some(3.into())]
+		);
+	}
+
+	#[test]
+	fn falls_back_during_an_interrupted_compaction() {
+		let path: String = directory("interrupted");
+		let cache: Cache = new_cache(&path, Limits::default());
+		cache.open_aof(FsyncPolicy::Always).unwrap();
+		set(&cache, "a", 1);
+		cache.save().unwrap();
+		set(&cache, "b", 2);
+		cache.save().unwrap();
+		set(&cache, "c", 3);
+		cache.begin_compaction().unwrap();
+		set(&cache, "d", 4);
+		drop(cache);
+
+		corrupt_snapshot(&path);
+		assert_eq!(
+			values(&reload(&path), &["a", "b", "c", "d"]),
+			vec![
+				Some(1.into()),
+				Some(2.into()),
+				Some(3.into()),
+				Some(4.into())
+			]
+		);
+	}
+
+	#[test]
+	fn fails_when_no_snapshot_can_be_loaded() {
+		let path: String = directory("unreadable");
+		let cache: Cache = new_cache(&path, Limits::default());
+		set(&cache, "a", 1);
+		cache.save().unwrap();
+		drop(cache);
+
+		corrupt_snapshot(&path);
+		assert!(new_cache(&path, Limits::default()).load().is_err());
+		// Nothing was moved, so the corrupt file can still be looked into.
+		assert!(snapshot::snapshot_path(&path).exists());
+	}
+
+	#[test]
+	fn spreads_keys_over_the_shards() {
+		let cache: Cache = new_cache("", Limits::default());
+		for key in keys(200) {
+			set(&cache, &key, 1);
+		}
+
+		assert_eq!(cache.key_count(), 200);
+		for lock in cache.shards.iter() {
+			assert!(!lock.read().unwrap().cache.is_empty());
+		}
+		let mut listed: Vec<String> = cache.list(usize::MAX, 0, "");
+		listed.sort_unstable();
+		let mut expected: Vec<String> = keys(200);
+		expected.sort_unstable();
+		assert_eq!(listed, expected);
+	}
+
+	#[test]
+	fn lists_keys_in_insertion_order_across_shards() {
+		let cache: Cache = Cache::new(
+			String::new(),
+			true,
+			SnapshotOptions::default(),
+			Limits::default(),
+			4,
+		);
+		for key in keys(20) {
+			set(&cache, &key, 1);
+		}
+		set(&cache, "other", 1);
+
+		assert_eq!(cache.list(usize::MAX, 0, "key-"), keys(20));
+		assert_eq!(cache.list(5, 5, "key-"), keys(10)[5..]);
+	}
+
+	#[test]
+	fn saves_and_loads_every_shard() {
+		let path: String = directory("shards");
+		let cache: Cache = new_cache(&path, Limits::default());
+		for key in keys(50) {
+			set(&cache, &key, 1);
+		}
+		cache.save().unwrap();
+		drop(cache);
+
+		let cache: Cache = new_cache(&path, Limits::default());
+		cache.load().unwrap();
+		assert_eq!(cache.key_count(), 50);
+		for key in keys(50) {
+			assert!(cache.read(&key).get(&key).is_some());
+		}
+	}
+
+	#[test]
+	fn flushes_every_shard() {
+		let cache: Cache = new_cache("", Limits::default());
+		for key in keys(50) {
+			set(&cache, &key, 1);
+		}
+		cache.flush();
+
+		assert_eq!(cache.key_count(), 0);
+		assert!(cache.list(usize::MAX, 0, "").is_empty());
+	}
+
+	/// A key that is not in the shard of `key`.
+	fn key_elsewhere(cache: &Cache, key: &str) -> String {
+		(0..)
+			.map(|i| format!("other-{}", i))
+			.find(|other| cache.index(other) != cache.index(key))
+			.unwrap()
+	}
+
+	#[test]
+	fn does_not_evict_under_a_transaction() {
+		let cache: Cache = new_cache(
+			"",
+			Limits {
+				max_keys: 1,
+				policy: EvictionPolicy::AllkeysLru,
+				..Limits::default()
+			},
+		);
+		set(&cache, "a", 1);
+		let other: String = key_elsewhere(&cache, "a");
+
+		let transaction = cache
+			.transaction_lock(cache.index("a"))
+			.try_write()
+			.unwrap();
+		let result = cache.write(&other).set(other.clone(), 2.into(), 0);
+		assert!(matches!(result, Err(ErrorCode::OutOfMemory)));
+		assert_eq!(value(&cache, "a"), Some(1.into()));
+
+		drop(transaction);
+		set(&cache, &other, 2);
+		assert_eq!(values(&cache, &["a", &other]), vec![None, Some(2.into())]);
+	}
+
+	#[test]
+	fn waits_for_a_busy_shard_to_evict_from() {
+		let cache: Cache = new_cache(
+			"",
+			Limits {
+				max_keys: 1,
+				policy: EvictionPolicy::AllkeysLru,
+				..Limits::default()
+			},
+		);
+		// The shard of the new key comes first, so its writer may wait for the other.
+		let (old, new): (String, String) = (0..)
+			.map(|i| (format!("old-{}", i), format!("new-{}", i)))
+			.find(|(old, new)| cache.index(new) < cache.index(old))
+			.unwrap();
+		set(&cache, &old, 1);
+
+		let busy: ShardWriteGuard = cache.write(&old);
+		std::thread::scope(|scope| {
+			let writer = scope.spawn(|| cache.write(&new).set(new.clone(), 2.into(), 0));
+			std::thread::sleep(std::time::Duration::from_millis(20));
+			drop(busy);
+			assert!(writer.join().unwrap().is_ok());
+		});
+		assert_eq!(values(&cache, &[&old, &new]), vec![None, Some(2.into())]);
+	}
+
+	#[test]
+	fn lists_expiring_keys_without_removing_expired_ones() {
+		let cache: Cache = new_cache("", Limits::default());
+		cache.write("a").set("a".to_string(), 1.into(), 1).unwrap();
+		cache
+			.write("b")
+			.set("b".to_string(), 1.into(), 60_000)
+			.unwrap();
+		cache.write("c").set("c".to_string(), 1.into(), 0).unwrap();
+		std::thread::sleep(std::time::Duration::from_millis(5));
+
+		let expiring: Vec<(String, u128)> = cache.expiring(120_000, 10);
+		assert_eq!(expiring.len(), 1);
+		assert_eq!(expiring[0].0, "b");
+		assert_eq!(cache.key_count(), 3);
+	}
 }
@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::eviction::value_size;
+use super::hash::Hash;
+use super::list::List;
+use super::set::Set;
+use super::zset::SortedSet;
+
+/// Type of the data stored under a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataType {
+	/// Plain JSON value
+	#[default]
+	Value,
+	/// Field-value pairs
+	Hash,
+	/// Ordered values
+	List,
+	/// Unique string members
+	Set,
+	/// Unique string members ordered by score
+	SortedSet,
+}
+
+impl DataType {
+	pub fn is_value(&self) -> bool {
+		*self == DataType::Value
+	}
+
+	pub fn from_u8(tag: u8) -> Option<Self> {
+		match tag {
+			0 => Some(DataType::Value),
+			1 => Some(DataType::Hash),
+			2 => Some(DataType::List),
+			3 => Some(DataType::Set),
+			4 => Some(DataType::SortedSet),
+			_ => None,
+		}
+	}
+}
+
+/// Contents of a key: a plain JSON value or one of the native data types.
+#[derive(Debug, Clone)]
+pub enum Data {
+	Value(Value),
+	Hash(Hash),
+	List(List),
+	Set(Set),
+	SortedSet(SortedSet),
+}
+
+impl Data {
+	pub fn data_type(&self) -> DataType {
+		match self {
+			Data::Value(_) => DataType::Value,
+			Data::Hash(_) => DataType::Hash,
+			Data::List(_) => DataType::List,
+			Data::Set(_) => DataType::Set,
+			Data::SortedSet(_) => DataType::SortedSet,
+		}
+	}
+
+	/// Rough number of bytes, tracked while the native types are changed.
+	pub fn size(&self) -> u64 {
+		match self {
+			Data::Value(value) => value_size(value),
+			Data::Hash(hash) => hash.size(),
+			Data::List(list) => list.size(),
+			Data::Set(set) => set.size(),
+			Data::SortedSet(set) => set.size(),
+		}
+	}
+
+	/// Native types without elements are removed, plain values never count as empty.
+	pub fn is_empty(&self) -> bool {
+		match self {
+			Data::Value(_) => false,
+			Data::Hash(hash) => hash.is_empty(),
+			Data::List(list) => list.is_empty(),
+			Data::Set(set) => set.is_empty(),
+			Data::SortedSet(set) => set.is_empty(),
+		}
+	}
+
+	/// JSON form written to snapshots, restored with `from_json` and the type.
+	pub fn to_json(&self) -> Value {
+		match self {
+			Data::Value(value) => value.clone(),
+			Data::Hash(hash) => hash.to_json(),
+			Data::List(list) => list.to_json(),
+			Data::Set(set) => set.to_json(),
+			Data::SortedSet(set) => set.to_json(),
+		}
+	}
+
+	pub fn from_json(data_type: DataType, value: Value) -> Option<Self> {
+		match data_type {
+			DataType::Value => Some(Data::Value(value)),
+			DataType::Hash => Hash::from_json(value).map(Data::Hash),
+			DataType::List => List::from_json(value).map(Data::List),
+			DataType::Set => Set::from_json(value).map(Data::Set),
+			DataType::SortedSet => SortedSet::from_json(value).map(Data::SortedSet),
+		}
+	}
+}
+
+/// A native data type that can be stored in `Data`.
+pub trait Collection: Default + Into<Data> {
+	fn from_data(data: &Data) -> Option<&Self>;
+	fn from_data_mut(data: &mut Data) -> Option<&mut Self>;
+}
+
+impl From<Hash> for Data {
+	fn from(hash: Hash) -> Self {
+		Data::Hash(hash)
+	}
+}
+
+impl Collection for Hash {
+	fn from_data(data: &Data) -> Option<&Self> {
+		match data {
+			Data::Hash(hash) => Some(hash),
+			_ => None,
+		}
+	}
+
+	fn from_data_mut(data: &mut Data) -> Option<&mut Self> {
+		match data {
+			Data::Hash(hash) => Some(hash),
+			_ => None,
+		}
+	}
+}
+
+impl From<List> for Data {
+	fn from(list: List) -> Self {
+		Data::List(list)
+	}
+}
+
+impl Collection for List {
+	fn from_data(data: &Data) -> Option<&Self> {
+		match data {
+			Data::List(list) => Some(list),
+			_ => None,
+		}
+	}
+
+	fn from_data_mut(data: &mut Data) -> Option<&mut Self> {
+		match data {
+			Data::List(list) => Some(list),
+			_ => None,
+		}
+	}
+}
+
+impl From<Set> for Data {
+	fn from(set: Set) -> Self {
+		Data::Set(set)
+	}
+}
+
+impl Collection for Set {
+	fn from_data(data: &Data) -> Option<&Self> {
+		match data {
+			Data::Set(set) => Some(set),
+			_ => None,
+		}
+	}
+
+	fn from_data_mut(data: &mut Data) -> Option<&mut Self> {
+		match data {
+			Data::Set(set) => Some(set),
+			_ => None,
+		}
+	}
+}
+
+impl From<SortedSet> for Data {
+	fn from(set: SortedSet) -> Self {
+		Data::SortedSet(set)
+	}
+}
+
+impl Collection for SortedSet {
+	fn from_data(data: &Data) -> Option<&Self> {
+		match data {
+			Data::SortedSet(set) => Some(set),
+			_ => None,
+		}
+	}
+
+	fn from_data_mut(data: &mut Data) -> Option<&mut Self> {
+		match data {
+			Data::SortedSet(set) => Some(set),
+			_ => None,
+		}
+	}
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+/// Events buffered for each listener, one that falls further behind misses some.
+pub const EVENT_BUFFER: usize = 4096;
+
+/// What happened to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+	/// A value was stored
+	Set,
+	/// A counter was incremented or decremented
+	Incr,
+	/// A hash, list, set or sorted set was changed
+	Update,
+	/// The expiration was changed or removed
+	Expire,
+	/// The key was deleted, also when the last element of a collection was removed
+	Del,
+	/// The key was removed because it expired
+	Expired,
+	/// The key was removed to stay within the memory or key limit
+	Evicted,
+	/// All keys were removed, sent once with an empty key
+	Flush,
+}
+
+impl Event {
+	pub fn name(self) -> &'static str {
+		match self {
+			Event::Set => "set",
+			Event::Incr => "incr",
+			Event::Update => "update",
+			Event::Expire => "expire",
+			Event::Del => "del",
+			Event::Expired => "expired",
+			Event::Evicted => "evicted",
+			Event::Flush => "flush",
+		}
+	}
+}
+
+/// A change to a key, see `Cache::events`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyEvent {
+	pub event: Event,
+	pub key: String,
+}
@@ -0,0 +1,121 @@
+use clap::ValueEnum;
+use rand::Rng;
+use serde_json::Value;
+use std::mem::size_of;
+
+use super::cache::CacheItem;
+use super::data::Data;
+
+/// Keys compared when picking a victim for the LRU and LFU policies.
+pub const EVICTION_SAMPLE: usize = 5;
+/// Frequency counter of newly written keys, so they are not evicted right away.
+pub const LFU_INIT: u8 = 5;
+
+/// Approximate bookkeeping cost of one entry: the map slot, the key string,
+/// the item itself and its place in the expiration index.
+const ENTRY_OVERHEAD: u64 = (size_of::<(String, CacheItem)>() + 64) as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum EvictionPolicy {
+	/// Reject writes once the limit is reached
+	#[default]
+	Noeviction,
+	/// Evict the least recently used keys
+	AllkeysLru,
+	/// Evict the least frequently used keys
+	AllkeysLfu,
+	/// Evict the keys closest to expiring, only keys with a TTL are considered
+	VolatileTtl,
+	/// Evict random keys
+	Random,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+	/// Estimated memory limit in bytes, 0 for unlimited
+	pub max_memory: u64,
+	/// Maximum number of keys, 0 for unlimited
+	pub max_keys: usize,
+	pub policy: EvictionPolicy,
+}
+
+impl Limits {
+	pub fn exceeded(&self, used_memory: u64, keys: usize) -> bool {
+		(self.max_memory > 0 && used_memory > self.max_memory)
+			|| (self.max_keys > 0 && keys > self.max_keys)
+	}
+}
+
+/// Rough number of bytes an entry for `key` holding `data` takes.
+pub fn entry_size(key: &str, data: &Data) -> u64 {
+	ENTRY_OVERHEAD + key.len() as u64 + data.size()
+}
+
+/// Rough number of bytes `value` takes besides its own slot.
+pub fn value_size(value: &Value) -> u64 {
+	let value_slot: u64 = size_of::<Value>() as u64;
+	match value {
+		Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+		Value::String(s) => s.len() as u64,
+		Value::Array(array) => array.iter().map(|v| value_slot + value_size(v)).sum(),
+		Value::Object(object) => object
+			.iter()
+			.map(|(k, v)| size_of::<String>() as u64 + k.len() as u64 + value_slot + value_size(v))
+			.sum(),
+	}
+}
+
+/// Logarithmic access counter: the more often a key was used, the less likely
+/// another access increments it, so 255 covers millions of accesses.
+pub fn lfu_increment(frequency: u8) -> u8 {
+	if frequency == u8::MAX {
+		return frequency;
+	}
+	let base: f64 = frequency.saturating_sub(LFU_INIT) as f64;
+	if rand::rng().random::<f64>() < 1.0 / (base * 10.0 + 1.0) {
+		frequency + 1
+	} else {
+		frequency
+	}
+}
+
+/// Lowers the access counter by one for every minute the key was not used,
+/// so keys that were popular a long time ago can be evicted too.
+pub fn lfu_decay(frequency: u8, accessed: u128, current_time: u128) -> u8 {
+	let idle_minutes: u128 = current_time.saturating_sub(accessed) / 60_000;
+	frequency.saturating_sub(idle_minutes.min(u8::MAX as u128) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn exceeds_only_the_limits_that_are_set() {
+		let unlimited: Limits = Limits::default();
+		assert!(!unlimited.exceeded(u64::MAX, usize::MAX));
+
+		let limits: Limits = Limits {
+			max_memory: 100,
+			max_keys: 2,
+			policy: EvictionPolicy::AllkeysLru,
+		};
+		assert!(!limits.exceeded(100, 2));
+		assert!(limits.exceeded(101, 2));
+		assert!(limits.exceeded(100, 3));
+	}
+
+	#[test]
+	fn counts_frequencies_up_to_the_maximum() {
+		assert_eq!(lfu_increment(u8::MAX), u8::MAX);
+		// Counters at the initial value always go up.
+		assert_eq!(lfu_increment(LFU_INIT), LFU_INIT + 1);
+	}
+
+	#[test]
+	fn decays_frequencies_by_idle_minutes() {
+		assert_eq!(lfu_decay(10, 0, 59_999), 10);
+		assert_eq!(lfu_decay(10, 0, 3 * 60_000), 7);
+		assert_eq!(lfu_decay(10, 0, u128::MAX), 0);
+	}
+}
@@ -0,0 +1,159 @@
+use indexmap::IndexMap;
+use serde_json::Value;
+use std::mem::size_of;
+
+use super::aof::AofEntry;
+use super::cache::ShardWriteGuard;
+use super::eviction::value_size;
+use crate::error::ErrorCode;
+
+/// Field-value pairs stored under a single key.
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+	fields: IndexMap<String, Value>,
+	/// Estimated size of all fields, see `eviction::value_size`.
+	size: u64,
+}
+
+impl Hash {
+	pub fn get(&self, field: &str) -> Option<&Value> {
+		self.fields.get(field)
+	}
+
+	pub fn contains(&self, field: &str) -> bool {
+		self.fields.contains_key(field)
+	}
+
+	/// Sets `field` to `value` and returns `true` when the field is new.
+	pub fn insert(&mut self, field: String, value: Value) -> bool {
+		if let Some(old) = self.fields.get(&field) {
+			self.size -= field_size(&field, old);
+		}
+		self.size += field_size(&field, &value);
+		self.fields.insert(field, value).is_none()
+	}
+
+	pub fn remove(&mut self, field: &str) -> bool {
+		match self.fields.swap_remove(field) {
+			Some(value) => {
+				self.size -= field_size(field, &value);
+				true
+			}
+			None => false,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.fields.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.fields.is_empty()
+	}
+
+	pub fn size(&self) -> u64 {
+		self.size
+	}
+
+	pub fn keys(&self) -> impl Iterator<Item = &String> {
+		self.fields.keys()
+	}
+
+	pub fn to_json(&self) -> Value {
+		Value::Object(
+			self
+				.fields
+				.iter()
+				.map(|(field, value)| (field.clone(), value.clone()))
+				.collect(),
+		)
+	}
+
+	pub fn from_json(value: Value) -> Option<Self> {
+		let Value::Object(object) = value else {
+			return None;
+		};
+		let mut hash: Hash = Hash::default();
+		for (field, value) in object {
+			hash.insert(field, value);
+		}
+		Some(hash)
+	}
+}
+
+fn field_size(field: &str, value: &Value) -> u64 {
+	(size_of::<(String, Value)>() + field.len()) as u64 + value_size(value)
+}
+
+impl ShardWriteGuard<'_> {
+	/// Sets the given fields of the hash under `key`, creating it when needed.
+	/// Returns how many of the fields are new.
+	pub fn hset(&mut self, key: &str, fields: IndexMap<String, Value>) -> Result<usize, ErrorCode> {
+		let added: Option<usize> = self.modify(key, true, |hash: &mut Hash| {
+			let mut added: usize = 0;
+			for (field, value) in &fields {
+				if hash.insert(field.clone(), value.clone()) {
+					added += 1;
+				}
+			}
+			Ok(added)
+		})?;
+
+		for (f, v) in fields {
+			self.log(|| AofEntry::Hset {
+				k: key.to_string(),
+				f,
+				v,
+			});
+		}
+		Ok(added.unwrap_or(0))
+	}
+
+	/// Removes the given fields and returns how many existed.
+	/// The hash is deleted together with its last field.
+	pub fn hdel(&mut self, key: &str, fields: Vec<String>) -> Result<usize, ErrorCode> {
+		let removed: Vec<String> = self
+			.modify(key, false, |hash: &mut Hash| {
+				Ok(
+					fields
+						.into_iter()
+						.filter(|field| hash.remove(field))
+						.collect(),
+				)
+			})?
+			.unwrap_or_default();
+
+		for f in &removed {
+			self.log(|| AofEntry::Hdel {
+				k: key.to_string(),
+				f: f.clone(),
+			});
+		}
+		Ok(removed.len())
+	}
+
+	/// Adds `delta` to the integer stored in `field`, a missing field counts as 0.
+	/// Returns the new value.
+	pub fn hincrby(&mut self, key: &str, field: &str, delta: i64) -> Result<i64, ErrorCode> {
+		let value: Option<i64> = self.modify(key, true, |hash: &mut Hash| {
+			let current: i64 = match hash.get(field) {
+				Some(Value::Number(n)) => n.as_i64().ok_or(ErrorCode::InvalidInteger)?,
+				Some(_) => return Err(ErrorCode::InvalidNumber),
+				None => 0,
+			};
+			let value: i64 = current
+				.checked_add(delta)
+				.ok_or(ErrorCode::IntegerOverflow)?;
+			hash.insert(field.to_string(), Value::from(value));
+			Ok(value)
+		})?;
+
+		let value: i64 = value.unwrap_or_default();
+		self.log(|| AofEntry::Hset {
+			k: key.to_string(),
+			f: field.to_string(),
+			v: Value::from(value),
+		});
+		Ok(value)
+	}
+}
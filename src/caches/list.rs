@@ -0,0 +1,411 @@
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+use super::aof::AofEntry;
+use super::cache::ShardWriteGuard;
+use super::eviction::value_size;
+use crate::error::ErrorCode;
+use crate::utils::current_time;
+
+/// Ordered values stored under a single key.
+///
+/// Every element has an absolute id, `head` being the id of the first one,
+/// so the append-only log can name the element a push or pop applies to.
+/// Replaying an entry that no longer fits the list then changes nothing.
+#[derive(Debug, Clone, Default)]
+pub struct List {
+	items: VecDeque<Value>,
+	head: i64,
+	/// Estimated size of all items, see `eviction::value_size`.
+	size: u64,
+}
+
+impl List {
+	/// Adds `value` in front of the element `id + 1`, an empty list starts at `id`.
+	/// Returns `false` when `id` is not next to the first element.
+	pub fn push_front(&mut self, id: i64, value: Value) -> bool {
+		if !self.items.is_empty() && id != self.head - 1 {
+			return false;
+		}
+		self.head = id;
+		self.size += item_size(&value);
+		self.items.push_front(value);
+		true
+	}
+
+	/// Adds `value` after the element `id - 1`, an empty list starts at `id`.
+	/// Returns `false` when `id` is not next to the last element.
+	pub fn push_back(&mut self, id: i64, value: Value) -> bool {
+		if self.items.is_empty() {
+			self.head = id;
+		} else if id != self.tail() + 1 {
+			return false;
+		}
+		self.size += item_size(&value);
+		self.items.push_back(value);
+		true
+	}
+
+	/// Removes the first element when its id is `id`.
+	pub fn pop_front(&mut self, id: i64) -> Option<Value> {
+		if self.items.is_empty() || id != self.head {
+			return None;
+		}
+		let value: Value = self.items.pop_front()?;
+		self.head += 1;
+		self.size -= item_size(&value);
+		Some(value)
+	}
+
+	/// Removes the last element when its id is `id`.
+	pub fn pop_back(&mut self, id: i64) -> Option<Value> {
+		if self.items.is_empty() || id != self.tail() {
+			return None;
+		}
+		let value: Value = self.items.pop_back()?;
+		self.size -= item_size(&value);
+		Some(value)
+	}
+
+	/// Keeps only the elements with ids from `first` to `last`, both included.
+	pub fn retain(&mut self, first: i64, last: i64) {
+		let first: i64 = first.max(self.head);
+		let last: i64 = last.min(self.tail());
+		if first > last {
+			self.items.clear();
+			self.size = 0;
+			return;
+		}
+
+		let mut removed: Vec<Value> = self.items.drain(..(first - self.head) as usize).collect();
+		removed.extend(self.items.drain((last - first + 1) as usize..));
+		self.size -= removed.iter().map(item_size).sum::<u64>();
+		self.head = first;
+	}
+
+	pub fn get(&self, index: i64) -> Option<&Value> {
+		let index: usize = self.position(index)?;
+		self.items.get(index)
+	}
+
+	/// Elements from `start` to `stop`, both included. Negative positions
+	/// count from the end and out of range positions are clamped.
+	pub fn range(&self, start: i64, stop: i64) -> Vec<&Value> {
+		match self.bounds(start, stop) {
+			Some((start, stop)) => self.items.range(start..=stop).collect(),
+			None => Vec::new(),
+		}
+	}
+
+	/// Ids of the elements `range` would return, used to log a trim.
+	pub fn ids(&self, start: i64, stop: i64) -> (i64, i64) {
+		match self.bounds(start, stop) {
+			Some((start, stop)) => (self.head + start as i64, self.head + stop as i64),
+			None => (self.head, self.head - 1),
+		}
+	}
+
+	pub fn head(&self) -> i64 {
+		self.head
+	}
+
+	pub fn tail(&self) -> i64 {
+		self.head + self.items.len() as i64 - 1
+	}
+
+	pub fn len(&self) -> usize {
+		self.items.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.items.is_empty()
+	}
+
+	pub fn size(&self) -> u64 {
+		self.size
+	}
+
+	/// Snapshot form, the head is kept so logged ids still match after a restart.
+	pub fn to_json(&self) -> Value {
+		serde_json::json!({ "head": self.head, "items": self.items })
+	}
+
+	pub fn from_json(value: Value) -> Option<Self> {
+		let head: i64 = value.get("head")?.as_i64()?;
+		let Some(Value::Array(items)) = value.get("items") else {
+			return None;
+		};
+		let mut list: List = List::default();
+		for (id, item) in (head..).zip(items) {
+			list.push_back(id, item.clone());
+		}
+		Some(list)
+	}
+
+	fn position(&self, index: i64) -> Option<usize> {
+		let index: i64 = if index < 0 {
+			self.items.len() as i64 + index
+		} else {
+			index
+		};
+		usize::try_from(index).ok()
+	}
+
+	fn bounds(&self, start: i64, stop: i64) -> Option<(usize, usize)> {
+		let len: i64 = self.items.len() as i64;
+		let start: i64 = if start < 0 {
+			(len + start).max(0)
+		} else {
+			start
+		};
+		let stop: i64 = if stop < 0 {
+			len + stop
+		} else {
+			stop.min(len - 1)
+		};
+		if start > stop || start >= len {
+			return None;
+		}
+		Some((start as usize, stop as usize))
+	}
+}
+
+fn item_size(value: &Value) -> u64 {
+	size_of::<Value>() as u64 + value_size(value)
+}
+
+/// Id of the first element of a new list. Ids are derived from the time so
+/// a list created under the key of a deleted one does not reuse its ids.
+fn first_id() -> i64 {
+	(current_time() as i64) << 20
+}
+
+/// End of a list that is pushed to or popped from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+	Front,
+	Back,
+}
+
+impl ShardWriteGuard<'_> {
+	/// Adds `values` one after another to the given end of the list under `key`,
+	/// creating it when needed. Returns the new length.
+	pub fn push(&mut self, key: &str, end: End, values: Vec<Value>) -> Result<usize, ErrorCode> {
+		let pushed: Option<(usize, Vec<(i64, Value)>)> =
+			self.modify(key, true, |list: &mut List| {
+				let mut pushed: Vec<(i64, Value)> = Vec::with_capacity(values.len());
+				for value in values {
+					let id: i64 = match (list.is_empty(), end) {
+						(true, _) => first_id(),
+						(false, End::Front) => list.head() - 1,
+						(false, End::Back) => list.tail() + 1,
+					};
+					match end {
+						End::Front => list.push_front(id, value.clone()),
+						End::Back => list.push_back(id, value.clone()),
+					};
+					pushed.push((id, value));
+				}
+				Ok((list.len(), pushed))
+			})?;
+
+		let (len, pushed) = pushed.unwrap_or_default();
+		for (i, v) in pushed {
+			self.log(|| match end {
+				End::Front => AofEntry::Lpush {
+					k: key.to_string(),
+					i,
+					v,
+				},
+				End::Back => AofEntry::Rpush {
+					k: key.to_string(),
+					i,
+					v,
+				},
+			});
+		}
+		if len > 0 {
+			self.notify_pushed();
+		}
+		Ok(len)
+	}
+
+	/// Removes and returns the element at the given end of the list under `key`.
+	/// The list is deleted together with its last element.
+	pub fn pop(&mut self, key: &str, end: End) -> Result<Option<Value>, ErrorCode> {
+		let popped: Option<(i64, Option<Value>)> = self.modify(key, false, |list: &mut List| {
+			Ok(match end {
+				End::Front => (list.head(), list.pop_front(list.head())),
+				End::Back => (list.tail(), list.pop_back(list.tail())),
+			})
+		})?;
+
+		let Some((i, Some(value))) = popped else {
+			return Ok(None);
+		};
+		self.log(|| match end {
+			End::Front => AofEntry::Lpop {
+				k: key.to_string(),
+				i,
+			},
+			End::Back => AofEntry::Rpop {
+				k: key.to_string(),
+				i,
+			},
+		});
+		Ok(Some(value))
+	}
+
+	/// Keeps only the elements from `start` to `stop`, positions work as in `List::range`.
+	pub fn trim(&mut self, key: &str, start: i64, stop: i64) -> Result<(), ErrorCode> {
+		let ids: Option<(i64, i64)> = self.modify(key, false, |list: &mut List| {
+			let (first, last) = list.ids(start, stop);
+			list.retain(first, last);
+			Ok((first, last))
+		})?;
+
+		if let Some((f, l)) = ids {
+			self.log(|| AofEntry::Ltrim {
+				k: key.to_string(),
+				f,
+				l,
+			});
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Applies a logged list change like `Cache::apply` does.
+	fn apply(list: &mut List, entry: &AofEntry) {
+		match entry {
+			AofEntry::Lpush { i, v, .. } => {
+				list.push_front(*i, v.clone());
+			}
+			AofEntry::Rpush { i, v, .. } => {
+				list.push_back(*i, v.clone());
+			}
+			AofEntry::Lpop { i, .. } => {
+				list.pop_front(*i);
+			}
+			AofEntry::Rpop { i, .. } => {
+				list.pop_back(*i);
+			}
+			AofEntry::Ltrim { f, l, .. } => list.retain(*f, *l),
+			_ => unreachable!(),
+		}
+	}
+
+	fn state(list: &List) -> (i64, Vec<Value>, u64) {
+		(
+			list.head(),
+			list.range(0, -1).into_iter().cloned().collect(),
+			list.size(),
+		)
+	}
+
+	/// The changes made to a list starting at id 100, as they would be logged:
+	/// pushes to both ends, a pop from each end and a trim.
+	fn log() -> Vec<AofEntry> {
+		let k: String = "list".to_string();
+		vec![
+			AofEntry::Rpush {
+				k: k.clone(),
+				i: 100,
+				v: "a".into(),
+			},
+			AofEntry::Rpush {
+				k: k.clone(),
+				i: 101,
+				v: "b".into(),
+			},
+			AofEntry::Lpush {
+				k: k.clone(),
+				i: 99,
+				v: "z".into(),
+			},
+			AofEntry::Rpush {
+				k: k.clone(),
+				i: 102,
+				v: "c".into(),
+			},
+			AofEntry::Rpush {
+				k: k.clone(),
+				i: 103,
+				v: "d".into(),
+			},
+			AofEntry::Lpop {
+				k: k.clone(),
+				i: 99,
+			},
+			AofEntry::Rpop {
+				k: k.clone(),
+				i: 103,
+			},
+			AofEntry::Lpush {
+				k: k.clone(),
+				i: 99,
+				v: "y".into(),
+			},
+			AofEntry::Ltrim {
+				k: k.clone(),
+				f: 100,
+				l: 101,
+			},
+			AofEntry::Rpush {
+				k,
+				i: 102,
+				v: "e".into(),
+			},
+		]
+	}
+
+	fn replayed(mut list: List, entries: &[AofEntry]) -> List {
+		for entry in entries {
+			apply(&mut list, entry);
+		}
+		list
+	}
+
+	#[test]
+	fn replays_log() {
+		let list: List = replayed(List::default(), &log());
+		assert_eq!(
+			state(&list),
+			(
+				100,
+				vec!["a".into(), "b".into(), "e".into()],
+				["a", "b", "e"].map(|v| item_size(&v.into())).iter().sum()
+			)
+		);
+	}
+
+	#[test]
+	fn replaying_log_again_changes_nothing() {
+		let once: List = replayed(List::default(), &log());
+		let twice: List = replayed(once.clone(), &log());
+		assert_eq!(state(&twice), state(&once));
+	}
+
+	#[test]
+	fn replaying_log_over_any_snapshot_gives_the_same_list() {
+		// A snapshot taken during a compaction already contains some of the
+		// logged changes, which are replayed once more on startup.
+		let entries: Vec<AofEntry> = log();
+		let expected = state(&replayed(List::default(), &entries));
+		for taken in 0..=entries.len() {
+			let snapshot: Value = replayed(List::default(), &entries[..taken]).to_json();
+			let list: List = List::from_json(snapshot).unwrap();
+			assert_eq!(
+				state(&replayed(list, &entries)),
+				expected,
+				"snapshot after {} entries",
+				taken
+			);
+		}
+	}
+}
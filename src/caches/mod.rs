@@ -1,2 +1,12 @@
+pub mod aof;
 pub mod cache;
+pub mod data;
+pub mod events;
+pub mod eviction;
+pub mod hash;
+pub mod list;
+pub mod set;
+pub mod snapshot;
 pub mod stats;
+pub mod tree;
+pub mod zset;
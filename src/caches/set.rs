@@ -0,0 +1,294 @@
+use indexmap::IndexSet;
+use rand::Rng;
+use serde_json::Value;
+use std::mem::size_of;
+
+use super::aof::AofEntry;
+use super::cache::{Cache, ShardWriteGuard, ShardsReadGuard};
+use crate::error::ErrorCode;
+
+/// Most members SRANDMEMBER returns at once, as repeated ones are not limited
+/// by the size of the set.
+const MAX_RANDOM_COUNT: u64 = 100_000;
+
+/// Unique string members stored under a single key.
+#[derive(Debug, Clone, Default)]
+pub struct Set {
+	members: IndexSet<String>,
+	/// Estimated size of all members.
+	size: u64,
+}
+
+impl Set {
+	pub fn contains(&self, member: &str) -> bool {
+		self.members.contains(member)
+	}
+
+	/// Adds `member` and returns `true` when it was not there yet.
+	pub fn insert(&mut self, member: String) -> bool {
+		let size: u64 = member_size(&member);
+		let added: bool = self.members.insert(member);
+		if added {
+			self.size += size;
+		}
+		added
+	}
+
+	pub fn remove(&mut self, member: &str) -> bool {
+		let removed: bool = self.members.swap_remove(member);
+		if removed {
+			self.size -= member_size(member);
+		}
+		removed
+	}
+
+	/// Removes and returns a random member.
+	pub fn pop_random(&mut self) -> Option<String> {
+		if self.members.is_empty() {
+			return None;
+		}
+		let index: usize = rand::rng().random_range(0..self.members.len());
+		let member: String = self.members.swap_remove_index(index)?;
+		self.size -= member_size(&member);
+		Some(member)
+	}
+
+	/// Up to `count` distinct random members, or exactly `-count` members
+	/// that may repeat when `count` is negative. Fails when more than
+	/// `MAX_RANDOM_COUNT` members are asked for either way.
+	pub fn random(&self, count: i64) -> Result<Vec<&String>, ErrorCode> {
+		if count.unsigned_abs() > MAX_RANDOM_COUNT {
+			return Err(ErrorCode::OutOfRange);
+		}
+		let len: usize = self.members.len();
+		if len == 0 {
+			return Ok(Vec::new());
+		}
+
+		let mut rng = rand::rng();
+		if count < 0 {
+			return Ok(
+				(0..count.unsigned_abs())
+					.filter_map(|_| self.members.get_index(rng.random_range(0..len)))
+					.collect(),
+			);
+		}
+		Ok(
+			rand::seq::index::sample(&mut rng, len, (count as usize).min(len))
+				.into_iter()
+				.filter_map(|index| self.members.get_index(index))
+				.collect(),
+		)
+	}
+
+	pub fn len(&self) -> usize {
+		self.members.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.members.is_empty()
+	}
+
+	pub fn size(&self) -> u64 {
+		self.size
+	}
+
+	pub fn members(&self) -> impl Iterator<Item = &String> {
+		self.members.iter()
+	}
+
+	pub fn to_json(&self) -> Value {
+		Value::from_iter(self.members.iter().cloned())
+	}
+
+	pub fn from_json(value: Value) -> Option<Self> {
+		let Value::Array(members) = value else {
+			return None;
+		};
+		let mut set: Set = Set::default();
+		for member in members {
+			let Value::String(member) = member else {
+				return None;
+			};
+			set.insert(member);
+		}
+		Some(set)
+	}
+}
+
+fn member_size(member: &str) -> u64 {
+	(size_of::<String>() + member.len()) as u64
+}
+
+/// Operation combining the sets stored under several keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+	/// Members found in every set
+	Inter,
+	/// Members found in any set
+	Union,
+	/// Members of the first set found in none of the others
+	Diff,
+}
+
+impl SetOperation {
+	/// Combines `sets` in order, a missing key counts as an empty set.
+	pub fn apply(self, sets: &[Option<&Set>]) -> Vec<String> {
+		let Some((first, rest)) = sets.split_first() else {
+			return Vec::new();
+		};
+		let first = first.iter().flat_map(|set| set.members());
+
+		match self {
+			SetOperation::Inter => first
+				.filter(|member| {
+					rest
+						.iter()
+						.all(|set| set.is_some_and(|set| set.contains(member)))
+				})
+				.cloned()
+				.collect(),
+			SetOperation::Union => first
+				.chain(rest.iter().flatten().flat_map(|set| set.members()))
+				.cloned()
+				.collect::<IndexSet<String>>()
+				.into_iter()
+				.collect(),
+			SetOperation::Diff => first
+				.filter(|member| !rest.iter().flatten().any(|set| set.contains(member)))
+				.cloned()
+				.collect(),
+		}
+	}
+}
+
+impl ShardWriteGuard<'_> {
+	/// Adds `members` to the set under `key`, creating it when needed.
+	/// Returns how many of them are new.
+	pub fn sadd(&mut self, key: &str, members: Vec<String>) -> Result<usize, ErrorCode> {
+		let added: Vec<String> = self
+			.modify(key, true, |set: &mut Set| {
+				Ok(
+					members
+						.into_iter()
+						.filter(|member| set.insert(member.clone()))
+						.collect(),
+				)
+			})?
+			.unwrap_or_default();
+
+		for m in &added {
+			self.log(|| AofEntry::Sadd {
+				k: key.to_string(),
+				m: m.clone(),
+			});
+		}
+		Ok(added.len())
+	}
+
+	/// Removes `members` and returns how many existed.
+	/// The set is deleted together with its last member.
+	pub fn srem(&mut self, key: &str, members: Vec<String>) -> Result<usize, ErrorCode> {
+		let removed: Vec<String> = self
+			.modify(key, false, |set: &mut Set| {
+				Ok(
+					members
+						.into_iter()
+						.filter(|member| set.remove(member))
+						.collect(),
+				)
+			})?
+			.unwrap_or_default();
+
+		for m in &removed {
+			self.log(|| AofEntry::Srem {
+				k: key.to_string(),
+				m: m.clone(),
+			});
+		}
+		Ok(removed.len())
+	}
+
+	/// Removes and returns a random member of the set under `key`.
+	pub fn spop(&mut self, key: &str) -> Result<Option<String>, ErrorCode> {
+		let member: Option<String> = self
+			.modify(key, false, |set: &mut Set| Ok(set.pop_random()))?
+			.flatten();
+
+		if let Some(m) = &member {
+			self.log(|| AofEntry::Srem {
+				k: key.to_string(),
+				m: m.clone(),
+			});
+		}
+		Ok(member)
+	}
+}
+
+impl Cache {
+	/// Combines the sets under `keys` as seen at a single point in time.
+	/// Fails with `WrongType` when one of the keys holds something else.
+	pub fn combine_sets(
+		&self,
+		keys: &[String],
+		operation: SetOperation,
+	) -> Result<Vec<String>, ErrorCode> {
+		let shards: ShardsReadGuard = self.read_many(keys);
+		let sets: Vec<Option<&Set>> = keys
+			.iter()
+			.map(|key| shards.shard(key).collection::<Set>(key))
+			.collect::<Result<_, _>>()?;
+		Ok(operation.apply(&sets))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashSet;
+
+	fn set(len: usize) -> Set {
+		let mut set: Set = Set::default();
+		for member in 0..len {
+			set.insert(member.to_string());
+		}
+		set
+	}
+
+	#[test]
+	fn picks_distinct_members_up_to_the_size() {
+		let set: Set = set(10);
+		let picked: Vec<&String> = set.random(4).unwrap();
+		assert_eq!(picked.len(), 4);
+		assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 4);
+		assert_eq!(set.random(100).unwrap().len(), 10);
+		assert!(set.random(0).unwrap().is_empty());
+	}
+
+	#[test]
+	fn picks_repeated_members_for_negative_counts() {
+		let set: Set = set(2);
+		let picked: Vec<&String> = set.random(-50).unwrap();
+		assert_eq!(picked.len(), 50);
+		assert!(picked.iter().all(|member| set.contains(member)));
+		assert!(Set::default().random(-50).unwrap().is_empty());
+	}
+
+	#[test]
+	fn rejects_counts_over_the_limit() {
+		let set: Set = set(2);
+		assert_eq!(
+			set.random(-(MAX_RANDOM_COUNT as i64)).unwrap().len(),
+			MAX_RANDOM_COUNT as usize
+		);
+		assert!(matches!(
+			set.random(-(MAX_RANDOM_COUNT as i64) - 1),
+			Err(ErrorCode::OutOfRange)
+		));
+		assert!(matches!(set.random(i64::MIN), Err(ErrorCode::OutOfRange)));
+		assert!(matches!(
+			set.random(MAX_RANDOM_COUNT as i64 + 1),
+			Err(ErrorCode::OutOfRange)
+		));
+	}
+}
@@ -0,0 +1,406 @@
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::cache::CacheItemSmall;
+use super::data::DataType;
+use crate::utils::current_time;
+
+const MAGIC: &str = "RABBITKV";
+const VERSION: u32 = 2;
+const LZ4_SUFFIX: &str = "+lz4";
+
+/// Snapshot contents in cache order, keyed by cache key.
+pub type Items = IndexMap<String, CacheItemSmall>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SnapshotFormat {
+	/// Human readable JSON object
+	#[default]
+	Json,
+	/// Length-prefixed binary records
+	Binary,
+}
+
+impl SnapshotFormat {
+	fn encoding(&self) -> &'static str {
+		match self {
+			SnapshotFormat::Json => "json",
+			SnapshotFormat::Binary => "bin",
+		}
+	}
+}
+
+/// How snapshots are written. Reading always detects the format from the header.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SnapshotOptions {
+	pub format: SnapshotFormat,
+	pub compress: bool,
+}
+
+/// Header written on the first line of every snapshot:
+/// `RABBITKV <version> <encoding> <crc32 of body> <body length>`,
+/// where the encoding is `json` or `bin`, optionally followed by `+lz4`.
+struct Header {
+	version: u32,
+	encoding: String,
+	checksum: u32,
+	length: u64,
+}
+
+impl Header {
+	fn parse(line: &str) -> Option<Self> {
+		let mut parts = line.split(' ');
+		if parts.next()? != MAGIC {
+			return None;
+		}
+		let header: Header = Header {
+			version: parts.next()?.parse().ok()?,
+			encoding: parts.next()?.to_string(),
+			checksum: u32::from_str_radix(parts.next()?, 16).ok()?,
+			length: parts.next()?.parse().ok()?,
+		};
+		match parts.next() {
+			Some(_) => None,
+			None => Some(header),
+		}
+	}
+
+	fn format(&self) -> String {
+		format!(
+			"{} {} {} {:08x} {}\n",
+			MAGIC, self.version, self.encoding, self.checksum, self.length
+		)
+	}
+}
+
+pub fn snapshot_path(dir: &str) -> PathBuf {
+	PathBuf::from(format!("{}/cache.json", dir))
+}
+
+fn backup_path(dir: &str) -> PathBuf {
+	PathBuf::from(format!("{}/cache.json.bak", dir))
+}
+
+/// Loads the snapshot in `dir` and returns its items and size in bytes, or
+/// `Ok(None)` when there is no snapshot yet.
+pub fn read(dir: &str) -> io::Result<Option<(Items, u64)>> {
+	match read_file(&snapshot_path(dir)) {
+		Ok(snapshot) => Ok(Some(snapshot)),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+/// Loads the snapshot that was replaced by the current one, like `read`.
+pub fn read_backup(dir: &str) -> io::Result<(Items, u64)> {
+	read_file(&backup_path(dir))
+}
+
+/// Makes the previous snapshot the current one after the current one turned
+/// out to be corrupt. The corrupt file is moved aside rather than deleted, and
+/// so that the next save cannot rotate it into the backup slot.
+pub fn restore_backup(dir: &str) -> io::Result<()> {
+	let path: PathBuf = snapshot_path(dir);
+	let moved: String = format!("{}.corrupt-{}", path.display(), current_time());
+	fs::rename(&path, &moved)?;
+	fs::rename(backup_path(dir), &path)?;
+	sync_dir(dir)?;
+	eprintln!(
+		"Recovered from the previous snapshot, corrupt one moved to {}",
+		moved
+	);
+	Ok(())
+}
+
+/// Writes `items` to `dir` atomically and keeps the replaced snapshot as a backup.
+/// Returns the number of bytes written.
+pub fn write(dir: &str, items: &Items, options: SnapshotOptions) -> io::Result<u64> {
+	let tmp_path: PathBuf = PathBuf::from(format!("{}/cache.json.tmp", dir));
+	let size: u64 = write_file(&tmp_path, items, options)?;
+	install(dir, &tmp_path)?;
+	Ok(size)
+}
+
+/// Writes `items` to `path` and fsyncs it, without touching the current snapshot.
+pub fn write_file(path: &Path, items: &Items, options: SnapshotOptions) -> io::Result<u64> {
+	let mut encoding: String = options.format.encoding().to_string();
+	let mut body: Vec<u8> = match options.format {
+		SnapshotFormat::Json => serde_json::to_vec_pretty(items)?,
+		SnapshotFormat::Binary => encode_binary(items)?,
+	};
+	if options.compress {
+		body = lz4_flex::compress_prepend_size(&body);
+		encoding.push_str(LZ4_SUFFIX);
+	}
+
+	let header: Header = Header {
+		version: VERSION,
+		encoding,
+		checksum: crc32fast::hash(&body),
+		length: body.len() as u64,
+	};
+
+	let mut file: File = File::create(path)?;
+	file.write_all(header.format().as_bytes())?;
+	file.write_all(&body)?;
+	file.sync_all()?;
+	Ok(file.metadata()?.len())
+}
+
+/// Replaces the current snapshot with the complete file at `from`.
+pub fn install(dir: &str, from: &Path) -> io::Result<()> {
+	let path: PathBuf = snapshot_path(dir);
+	if path.exists() {
+		fs::rename(&path, backup_path(dir))?;
+	}
+	fs::rename(from, &path)?;
+	sync_dir(dir)
+}
+
+fn read_file(path: &Path) -> io::Result<(Items, u64)> {
+	let data: Vec<u8> = fs::read(path)?;
+	let size: u64 = data.len() as u64;
+
+	// Snapshots written before the header was introduced are plain JSON.
+	if !data.starts_with(MAGIC.as_bytes()) {
+		return Ok((serde_json::from_slice(&data)?, size));
+	}
+
+	let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+	let newline: usize = data
+		.iter()
+		.position(|&b| b == b'\n')
+		.ok_or_else(|| invalid("Snapshot header is incomplete"))?;
+	let header: Header = std::str::from_utf8(&data[..newline])
+		.ok()
+		.and_then(Header::parse)
+		.ok_or_else(|| invalid("Snapshot header is malformed"))?;
+	let body: &[u8] = &data[newline + 1..];
+
+	if !(1..=VERSION).contains(&header.version) {
+		return Err(invalid(&format!(
+			"Unsupported snapshot version {}",
+			header.version
+		)));
+	}
+	if body.len() as u64 != header.length {
+		return Err(invalid(&format!(
+			"Snapshot is truncated: expected {} bytes, found {}",
+			header.length,
+			body.len()
+		)));
+	}
+	if crc32fast::hash(body) != header.checksum {
+		return Err(invalid("Snapshot checksum does not match"));
+	}
+
+	let (encoding, decompressed) = match header.encoding.strip_suffix(LZ4_SUFFIX) {
+		Some(encoding) => (
+			encoding,
+			Some(lz4_flex::decompress_size_prepended(body).map_err(|err| invalid(&err.to_string()))?),
+		),
+		None => (header.encoding.as_str(), None),
+	};
+	let body: &[u8] = decompressed.as_deref().unwrap_or(body);
+
+	let items: Items = match encoding {
+		"json" => serde_json::from_slice(body)?,
+		"bin" => decode_binary(body, header.version)?,
+		_ => {
+			return Err(invalid(&format!(
+				"Unsupported snapshot encoding {}",
+				header.encoding
+			)))
+		}
+	};
+	Ok((items, size))
+}
+
+/// Binary layout, all integers little-endian:
+/// `count: u64`, then per item `key_len: u32`, key, `expiration: u128`,
+/// `type: u8` (since version 2), `value_len: u32` and the value as JSON.
+fn encode_binary(items: &Items) -> io::Result<Vec<u8>> {
+	let mut body: Vec<u8> = Vec::new();
+	body.extend_from_slice(&(items.len() as u64).to_le_bytes());
+	for (key, item) in items {
+		let value: Vec<u8> = serde_json::to_vec(&item.v)?;
+		body.extend_from_slice(&(key.len() as u32).to_le_bytes());
+		body.extend_from_slice(key.as_bytes());
+		body.extend_from_slice(&item.e.to_le_bytes());
+		body.push(item.t as u8);
+		body.extend_from_slice(&(value.len() as u32).to_le_bytes());
+		body.extend_from_slice(&value);
+	}
+	Ok(body)
+}
+
+fn decode_binary(mut body: &[u8], version: u32) -> io::Result<Items> {
+	fn take<'a>(body: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+		if body.len() < len {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"Binary snapshot ends unexpectedly",
+			));
+		}
+		let (head, tail) = body.split_at(len);
+		*body = tail;
+		Ok(head)
+	}
+	fn take_u32(body: &mut &[u8]) -> io::Result<usize> {
+		Ok(u32::from_le_bytes(take(body, 4)?.try_into().unwrap()) as usize)
+	}
+
+	let count: u64 = u64::from_le_bytes(take(&mut body, 8)?.try_into().unwrap());
+	let mut items: Items = IndexMap::new();
+	for _ in 0..count {
+		let key_len: usize = take_u32(&mut body)?;
+		let key: String = String::from_utf8(take(&mut body, key_len)?.to_vec())
+			.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+		let e: u128 = u128::from_le_bytes(take(&mut body, 16)?.try_into().unwrap());
+		let t: DataType = match version {
+			1 => DataType::Value,
+			_ => DataType::from_u8(take(&mut body, 1)?[0]).ok_or_else(|| {
+				io::Error::new(
+					io::ErrorKind::InvalidData,
+					"Unknown type in binary snapshot",
+				)
+			})?,
+		};
+		let value_len: usize = take_u32(&mut body)?;
+		let v: serde_json::Value = serde_json::from_slice(take(&mut body, value_len)?)?;
+		items.insert(key, CacheItemSmall { v, e, t });
+	}
+	Ok(items)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &str) -> io::Result<()> {
+	File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &str) -> io::Result<()> {
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn snapshot_file(name: &str) -> PathBuf {
+		std::env::temp_dir().join(format!(
+			"rabbit-kv-snapshot-{}-{}",
+			std::process::id(),
+			name
+		))
+	}
+
+	fn items() -> Items {
+		let mut items: Items = IndexMap::new();
+		items.insert(
+			"value".to_string(),
+			CacheItemSmall {
+				v: serde_json::json!({ "nested": [1, "two", null] }),
+				e: 0,
+				t: DataType::Value,
+			},
+		);
+		items.insert(
+			"hash".to_string(),
+			CacheItemSmall {
+				v: serde_json::json!({ "field": "x".repeat(100) }),
+				e: 1_700_000_000_000,
+				t: DataType::Hash,
+			},
+		);
+		items.insert(
+			"ключ".to_string(),
+			CacheItemSmall {
+				v: serde_json::json!(["a", "b"]),
+				e: 1234,
+				t: DataType::Value,
+			},
+		);
+		items
+	}
+
+	fn round_trip(name: &str, options: SnapshotOptions) {
+		let path: PathBuf = snapshot_file(name);
+		let size: u64 = write_file(&path, &items(), options).unwrap();
+		let (read, read_size) = read_file(&path).unwrap();
+		let data: Vec<u8> = fs::read(&path).unwrap();
+		fs::remove_file(&path).ok();
+
+		let header: &str =
+			std::str::from_utf8(&data[..data.iter().position(|&b| b == b'\n').unwrap()]).unwrap();
+		let encoding: &str = header.split(' ').nth(2).unwrap();
+		assert_eq!(encoding.ends_with(LZ4_SUFFIX), options.compress);
+		assert!(encoding.starts_with(options.format.encoding()));
+
+		assert_eq!(read_size, size);
+		let expected: Items = items();
+		assert!(read.keys().eq(expected.keys()));
+		for (item, expected) in read.values().zip(expected.values()) {
+			assert_eq!(item.v, expected.v);
+			assert_eq!(item.e, expected.e);
+			assert_eq!(item.t, expected.t);
+		}
+	}
+
+	#[test]
+	fn binary_round_trip() {
+		round_trip(
+			"binary",
+			SnapshotOptions {
+				format: SnapshotFormat::Binary,
+				compress: false,
+			},
+		);
+	}
+
+	#[test]
+	fn compressed_binary_round_trip() {
+		round_trip(
+			"binary-lz4",
+			SnapshotOptions {
+				format: SnapshotFormat::Binary,
+				compress: true,
+			},
+		);
+	}
+
+	#[test]
+	fn compressed_json_round_trip() {
+		round_trip(
+			"json-lz4",
+			SnapshotOptions {
+				format: SnapshotFormat::Json,
+				compress: true,
+			},
+		);
+	}
+
+	#[test]
+	fn rejects_checksum_mismatch() {
+		for compress in [false, true] {
+			let path: PathBuf = snapshot_file(&format!("crc-{}", compress));
+			let options: SnapshotOptions = SnapshotOptions {
+				format: SnapshotFormat::Binary,
+				compress,
+			};
+			write_file(&path, &items(), options).unwrap();
+			let mut data: Vec<u8> = fs::read(&path).unwrap();
+			let last: usize = data.len() - 1;
+			data[last] ^= 0xff;
+			fs::write(&path, &data).unwrap();
+
+			let err: io::Error = read_file(&path).unwrap_err();
+			fs::remove_file(&path).ok();
+			assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+			assert_eq!(err.to_string(), "Snapshot checksum does not match");
+		}
+	}
+}
@@ -1,9 +1,68 @@
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SaveStatus {
+	#[default]
+	None,
+	Ok,
+	Failed,
+}
+
+/// Operation counters of a single shard. They are atomic because reads
+/// update them while holding the shard lock in shared mode.
+#[derive(Debug, Default)]
+pub struct ShardCounters {
+	pub writes: AtomicU64,
+	pub reads: AtomicU64,
+	pub deletes: AtomicU64,
+	pub expired: AtomicU64,
+	pub evicted: AtomicU64,
+}
+
+impl ShardCounters {
+	pub fn changes(&self) -> u64 {
+		self.writes.load(Ordering::Relaxed) + self.deletes.load(Ordering::Relaxed)
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ShardStats {
+	pub keys: usize,
+	pub used_memory: u64,
+	pub writes: u64,
+	pub reads: u64,
+	pub deletes: u64,
+	pub expired: u64,
+	pub evicted: u64,
+}
+
+impl ShardStats {
+	pub fn new(keys: usize, used_memory: u64, counters: &ShardCounters) -> Self {
+		ShardStats {
+			keys,
+			used_memory,
+			writes: counters.writes.load(Ordering::Relaxed),
+			reads: counters.reads.load(Ordering::Relaxed),
+			deletes: counters.deletes.load(Ordering::Relaxed),
+			expired: counters.expired.load(Ordering::Relaxed),
+			evicted: counters.evicted.load(Ordering::Relaxed),
+		}
+	}
+}
+
+/// Totals over all shards, followed by the numbers of each shard.
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Stats {
 	pub writes: u64,
 	pub reads: u64,
 	pub deletes: u64,
 	pub lists: u64,
+	pub expired: u64,
+	pub evicted: u64,
+	pub last_save_time: u128,
+	pub last_save_duration: u128,
+	pub last_save_status: SaveStatus,
+	pub shards: Vec<ShardStats>,
 }
@@ -0,0 +1,288 @@
+use rand::Rng;
+use std::mem::size_of;
+use std::ops::Range;
+
+type Link<T> = Option<Box<Node<T>>>;
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+	value: T,
+	/// Random heap priority that keeps the tree balanced on average.
+	priority: u64,
+	/// Number of values in the subtree, used to find values by rank.
+	size: usize,
+	left: Link<T>,
+	right: Link<T>,
+}
+
+impl<T> Node<T> {
+	fn update(&mut self) {
+		self.size = 1 + size(&self.left) + size(&self.right);
+	}
+}
+
+/// Ordered values that can also be found by their rank, a treap where every
+/// node knows the size of its subtree. All operations take O(log n) on average.
+#[derive(Debug, Clone)]
+pub struct RankTree<T> {
+	root: Link<T>,
+}
+
+impl<T> Default for RankTree<T> {
+	fn default() -> Self {
+		RankTree { root: None }
+	}
+}
+
+impl<T: Ord> RankTree<T> {
+	/// Memory taken by a single value in the tree, besides what it owns.
+	pub const NODE_SIZE: usize = size_of::<Node<T>>();
+
+	pub fn len(&self) -> usize {
+		size(&self.root)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.root.is_none()
+	}
+
+	/// Adds `value`, which must not be in the tree yet.
+	pub fn insert(&mut self, value: T) {
+		let node: Box<Node<T>> = Box::new(Node {
+			value,
+			priority: rand::rng().random(),
+			size: 1,
+			left: None,
+			right: None,
+		});
+		let (less, rest) = split(self.root.take(), &|other: &T| *other < node.value);
+		self.root = merge(merge(less, Some(node)), rest);
+	}
+
+	pub fn remove(&mut self, value: &T) -> bool {
+		let (less, rest) = split(self.root.take(), &|other: &T| other < value);
+		let (found, greater) = split(rest, &|other: &T| other == value);
+		self.root = merge(less, greater);
+		found.is_some()
+	}
+
+	/// Number of leading values for which `pred` holds, `pred` must hold for
+	/// a prefix of the values only, like with `slice::partition_point`.
+	pub fn partition_point(&self, pred: impl Fn(&T) -> bool) -> usize {
+		let mut count: usize = 0;
+		let mut link: &Link<T> = &self.root;
+		while let Some(node) = link {
+			if pred(&node.value) {
+				count += size(&node.left) + 1;
+				link = &node.right;
+			} else {
+				link = &node.left;
+			}
+		}
+		count
+	}
+
+	/// Value with the given rank, 0 being the smallest.
+	pub fn get(&self, mut index: usize) -> Option<&T> {
+		let mut link: &Link<T> = &self.root;
+		while let Some(node) = link {
+			let left: usize = size(&node.left);
+			match index.cmp(&left) {
+				std::cmp::Ordering::Less => link = &node.left,
+				std::cmp::Ordering::Equal => return Some(&node.value),
+				std::cmp::Ordering::Greater => {
+					index -= left + 1;
+					link = &node.right;
+				}
+			}
+		}
+		None
+	}
+
+	/// Values with ranks in `range`, in order.
+	pub fn range(&self, range: Range<usize>) -> impl Iterator<Item = &T> {
+		range.map_while(|index| self.get(index))
+	}
+
+	/// Removes the values with ranks in `range` and returns them in order.
+	pub fn drain(&mut self, range: Range<usize>) -> Vec<T> {
+		let (less, rest) = split_at(self.root.take(), range.start);
+		let (middle, greater) = split_at(rest, range.end.saturating_sub(range.start));
+		self.root = merge(less, greater);
+
+		let mut values: Vec<T> = Vec::with_capacity(size(&middle));
+		into_values(middle, &mut values);
+		values
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = &T> {
+		self.range(0..self.len())
+	}
+}
+
+fn size<T>(link: &Link<T>) -> usize {
+	link.as_ref().map_or(0, |node| node.size)
+}
+
+/// Splits into the values for which `pred` holds and the rest.
+fn split<T>(link: Link<T>, pred: &impl Fn(&T) -> bool) -> (Link<T>, Link<T>) {
+	let Some(mut node) = link else {
+		return (None, None);
+	};
+	if pred(&node.value) {
+		let (less, rest) = split(node.right.take(), pred);
+		node.right = less;
+		node.update();
+		(Some(node), rest)
+	} else {
+		let (less, rest) = split(node.left.take(), pred);
+		node.left = rest;
+		node.update();
+		(less, Some(node))
+	}
+}
+
+/// Splits into the first `count` values and the rest.
+fn split_at<T>(link: Link<T>, count: usize) -> (Link<T>, Link<T>) {
+	let Some(mut node) = link else {
+		return (None, None);
+	};
+	let left: usize = size(&node.left);
+	if count > left {
+		let (less, rest) = split_at(node.right.take(), count - left - 1);
+		node.right = less;
+		node.update();
+		(Some(node), rest)
+	} else {
+		let (less, rest) = split_at(node.left.take(), count);
+		node.left = rest;
+		node.update();
+		(less, Some(node))
+	}
+}
+
+/// Joins two trees, all values of `less` must come before those of `greater`.
+fn merge<T>(less: Link<T>, greater: Link<T>) -> Link<T> {
+	match (less, greater) {
+		(None, link) | (link, None) => link,
+		(Some(mut less), Some(mut greater)) => {
+			if less.priority > greater.priority {
+				less.right = merge(less.right.take(), Some(greater));
+				less.update();
+				Some(less)
+			} else {
+				greater.left = merge(Some(less), greater.left.take());
+				greater.update();
+				Some(greater)
+			}
+		}
+	}
+}
+
+fn into_values<T>(link: Link<T>, values: &mut Vec<T>) {
+	if let Some(node) = link {
+		let node: Node<T> = *node;
+		into_values(node.left, values);
+		values.push(node.value);
+		into_values(node.right, values);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::BTreeSet;
+
+	/// Checks sizes, order and heap priorities below `link`, returns its size.
+	fn check(link: &Link<u32>, low: Option<u32>, high: Option<u32>, priority: u64) -> usize {
+		let Some(node) = link else {
+			return 0;
+		};
+		assert!(low.is_none_or(|low| low < node.value));
+		assert!(high.is_none_or(|high| node.value < high));
+		assert!(node.priority <= priority);
+		let size: usize = 1
+			+ check(&node.left, low, Some(node.value), node.priority)
+			+ check(&node.right, Some(node.value), high, node.priority);
+		assert_eq!(node.size, size);
+		size
+	}
+
+	fn assert_matches(tree: &RankTree<u32>, expected: &BTreeSet<u32>) {
+		assert_eq!(check(&tree.root, None, None, u64::MAX), expected.len());
+		assert_eq!(tree.len(), expected.len());
+		assert_eq!(tree.is_empty(), expected.is_empty());
+		assert!(tree.iter().eq(expected.iter()));
+	}
+
+	#[test]
+	fn keeps_invariants_under_random_changes() {
+		let mut rng = rand::rng();
+		let mut tree: RankTree<u32> = RankTree::default();
+		let mut expected: BTreeSet<u32> = BTreeSet::new();
+		for _ in 0..2000 {
+			let value: u32 = rng.random_range(0..300);
+			if rng.random_bool(0.6) {
+				if expected.insert(value) {
+					tree.insert(value);
+				}
+			} else {
+				assert_eq!(tree.remove(&value), expected.remove(&value));
+			}
+		}
+		assert_matches(&tree, &expected);
+	}
+
+	#[test]
+	fn finds_values_by_rank() {
+		let mut tree: RankTree<u32> = RankTree::default();
+		for value in (0..100).rev() {
+			tree.insert(value * 2);
+		}
+
+		for rank in 0..100 {
+			assert_eq!(tree.get(rank), Some(&(rank as u32 * 2)));
+			// The rank of a value is the number of values before it.
+			assert_eq!(tree.partition_point(|value| *value < rank as u32 * 2), rank);
+		}
+		assert_eq!(tree.get(100), None);
+		assert_eq!(tree.partition_point(|value| *value < 7), 4);
+		assert_eq!(tree.partition_point(|_| true), 100);
+	}
+
+	#[test]
+	fn ranges_and_drains_by_rank() {
+		let mut tree: RankTree<u32> = RankTree::default();
+		let mut expected: BTreeSet<u32> = (0..50).collect();
+		for value in expected.iter().rev() {
+			tree.insert(*value);
+		}
+
+		assert!(tree.range(10..15).copied().eq(10..15));
+		assert!(tree.range(45..60).copied().eq(45..50));
+		assert_eq!(tree.range(60..70).count(), 0);
+
+		assert_eq!(tree.drain(10..20), (10..20).collect::<Vec<u32>>());
+		expected.retain(|value| !(10..20).contains(value));
+		assert_matches(&tree, &expected);
+
+		assert_eq!(tree.drain(35..100), (45..50).collect::<Vec<u32>>());
+		expected.retain(|value| *value < 45);
+		assert_matches(&tree, &expected);
+
+		assert!(tree.drain(5..5).is_empty());
+		assert_eq!(tree.drain(0..tree.len()).len(), expected.len());
+		assert_matches(&tree, &BTreeSet::new());
+	}
+
+	#[test]
+	fn removing_missing_value_changes_nothing() {
+		let mut tree: RankTree<u32> = RankTree::default();
+		tree.insert(1);
+		tree.insert(3);
+		assert!(!tree.remove(&2));
+		assert!(tree.remove(&1));
+		assert!(!tree.remove(&1));
+		assert_matches(&tree, &BTreeSet::from([3]));
+	}
+}
@@ -0,0 +1,280 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use super::aof::AofEntry;
+use super::cache::ShardWriteGuard;
+use super::tree::RankTree;
+use crate::error::ErrorCode;
+
+/// Score of a sorted set member, never NaN so it can be totally ordered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Score {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.0.total_cmp(&other.0)
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoredMember {
+	pub member: String,
+	pub score: f64,
+}
+
+/// Unique members ordered by score, then by member for equal scores.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+	scores: HashMap<String, f64>,
+	ranks: RankTree<(Score, String)>,
+	/// Estimated size of all members.
+	size: u64,
+}
+
+impl SortedSet {
+	/// Sets the score of `member`, returns `true` when it was not there yet.
+	/// The score must be finite.
+	pub fn insert(&mut self, member: String, score: f64) -> bool {
+		let added: bool = match self.scores.get(&member) {
+			Some(old) => {
+				self.ranks.remove(&(Score(*old), member.clone()));
+				false
+			}
+			None => {
+				self.size += member_size(&member);
+				true
+			}
+		};
+		self.scores.insert(member.clone(), score);
+		self.ranks.insert((Score(score), member));
+		added
+	}
+
+	pub fn remove(&mut self, member: &str) -> bool {
+		match self.scores.remove(member) {
+			Some(score) => {
+				self.ranks.remove(&(Score(score), member.to_string()));
+				self.size -= member_size(member);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Removes the members scored from `min` to `max`, both included, and returns them.
+	pub fn remove_range_by_score(&mut self, min: f64, max: f64) -> Vec<String> {
+		let start: usize = self.ranks.partition_point(|(score, _)| score.0 < min);
+		let end: usize = self.ranks.partition_point(|(score, _)| score.0 <= max);
+		let removed: Vec<String> = self
+			.ranks
+			.drain(start..end.max(start))
+			.into_iter()
+			.map(|(_, member)| member)
+			.collect();
+		for member in &removed {
+			self.scores.remove(member);
+			self.size -= member_size(member);
+		}
+		removed
+	}
+
+	pub fn score(&self, member: &str) -> Option<f64> {
+		self.scores.get(member).copied()
+	}
+
+	/// Position of `member` in the set, 0 being the lowest score.
+	pub fn rank(&self, member: &str) -> Option<usize> {
+		let entry: (Score, String) = (Score(self.score(member)?), member.to_string());
+		Some(self.ranks.partition_point(|other| *other < entry))
+	}
+
+	/// Members ranked from `start` to `stop`, both included. Negative positions
+	/// count from the end and out of range positions are clamped.
+	pub fn range(&self, start: i64, stop: i64) -> Vec<ScoredMember> {
+		let len: i64 = self.len() as i64;
+		let start: i64 = if start < 0 {
+			(len + start).max(0)
+		} else {
+			start
+		};
+		let stop: i64 = if stop < 0 {
+			len + stop
+		} else {
+			stop.min(len - 1)
+		};
+		if start > stop || start >= len {
+			return Vec::new();
+		}
+		self
+			.ranks
+			.range(start as usize..stop as usize + 1)
+			.map(scored)
+			.collect()
+	}
+
+	/// Members scored from `min` to `max`, both included, skipping the first
+	/// `offset` of them and returning at most `count`.
+	pub fn range_by_score(
+		&self,
+		min: f64,
+		max: f64,
+		offset: usize,
+		count: usize,
+	) -> Vec<ScoredMember> {
+		let start: usize = self.ranks.partition_point(|(score, _)| score.0 < min);
+		let end: usize = self.ranks.partition_point(|(score, _)| score.0 <= max);
+		let start: usize = start.saturating_add(offset);
+		self
+			.ranks
+			.range(start..end.min(start.saturating_add(count)))
+			.map(scored)
+			.collect()
+	}
+
+	pub fn len(&self) -> usize {
+		self.scores.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.scores.is_empty()
+	}
+
+	pub fn size(&self) -> u64 {
+		self.size
+	}
+
+	/// Snapshot form, `[member, score]` pairs ordered by score.
+	pub fn to_json(&self) -> Value {
+		Value::from_iter(
+			self
+				.ranks
+				.iter()
+				.map(|(score, member)| serde_json::json!([member, score.0])),
+		)
+	}
+
+	pub fn from_json(value: Value) -> Option<Self> {
+		let Value::Array(pairs) = value else {
+			return None;
+		};
+		let mut set: SortedSet = SortedSet::default();
+		for pair in pairs {
+			let (member, score): (String, f64) = serde_json::from_value(pair).ok()?;
+			set.insert(member, score);
+		}
+		Some(set)
+	}
+}
+
+fn scored((score, member): &(Score, String)) -> ScoredMember {
+	ScoredMember {
+		member: member.clone(),
+		score: score.0,
+	}
+}
+
+fn member_size(member: &str) -> u64 {
+	// The member is stored twice, in the score map and in the rank tree.
+	(size_of::<(String, f64)>() + RankTree::<(Score, String)>::NODE_SIZE + 2 * member.len()) as u64
+}
+
+impl ShardWriteGuard<'_> {
+	/// Sets the scores of `members` in the sorted set under `key`, creating it
+	/// when needed. Returns how many of them are new.
+	pub fn zadd(&mut self, key: &str, members: Vec<ScoredMember>) -> Result<usize, ErrorCode> {
+		if members.iter().any(|member| !member.score.is_finite()) {
+			return Err(ErrorCode::InvalidFloat);
+		}
+		let added: usize = self
+			.modify(key, true, |set: &mut SortedSet| {
+				Ok(
+					members
+						.iter()
+						.filter(|member| set.insert(member.member.clone(), member.score))
+						.count(),
+				)
+			})?
+			.unwrap_or_default();
+
+		for member in members {
+			self.log(|| AofEntry::Zadd {
+				k: key.to_string(),
+				m: member.member,
+				s: member.score,
+			});
+		}
+		Ok(added)
+	}
+
+	/// Adds `delta` to the score of `member`, a missing member starts at 0.
+	/// Returns the new score.
+	pub fn zincrby(&mut self, key: &str, member: &str, delta: f64) -> Result<f64, ErrorCode> {
+		let score: f64 = self
+			.modify(key, true, |set: &mut SortedSet| {
+				let score: f64 = set.score(member).unwrap_or(0.0) + delta;
+				if !score.is_finite() {
+					return Err(ErrorCode::InvalidFloat);
+				}
+				set.insert(member.to_string(), score);
+				Ok(score)
+			})?
+			.unwrap_or_default();
+
+		self.log(|| AofEntry::Zadd {
+			k: key.to_string(),
+			m: member.to_string(),
+			s: score,
+		});
+		Ok(score)
+	}
+
+	/// Removes `members` and returns how many existed.
+	/// The sorted set is deleted together with its last member.
+	pub fn zrem(&mut self, key: &str, members: Vec<String>) -> Result<usize, ErrorCode> {
+		let removed: Vec<String> = self
+			.modify(key, false, |set: &mut SortedSet| {
+				Ok(
+					members
+						.into_iter()
+						.filter(|member| set.remove(member))
+						.collect(),
+				)
+			})?
+			.unwrap_or_default();
+
+		self.log_removed(key, &removed);
+		Ok(removed.len())
+	}
+
+	/// Removes the members scored from `min` to `max` and returns how many there were.
+	pub fn zremrangebyscore(&mut self, key: &str, min: f64, max: f64) -> Result<usize, ErrorCode> {
+		let removed: Vec<String> = self
+			.modify(key, false, |set: &mut SortedSet| {
+				Ok(set.remove_range_by_score(min, max))
+			})?
+			.unwrap_or_default();
+
+		self.log_removed(key, &removed);
+		Ok(removed.len())
+	}
+
+	fn log_removed(&self, key: &str, members: &[String]) {
+		for m in members {
+			self.log(|| AofEntry::Zrem {
+				k: key.to_string(),
+				m: m.clone(),
+			});
+		}
+	}
+}
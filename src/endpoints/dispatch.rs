@@ -0,0 +1,567 @@
+use std::sync::Arc;
+
+use crate::error::{Error, ErrorCode};
+use crate::state::Scope;
+use crate::types::{
+	Actions, BlockingPopPayload, CasPayload, CountPayload, DataPayload, EvalPayload, EvalShaPayload,
+	ExpireAtPayload, ExpirePayload, ExpiringPayload, FieldNumberPayload, FieldPayload, FieldsPayload,
+	FloatDataPayload, HashSetPayload, IndexPayload, KeyPayload, KeysPayload, ListPayload,
+	MemberPayload, MemberScorePayload, MembersPayload, MultiSetPayload, NumberDataPayload,
+	PublishPayload, PushPayload, RangePayload, ScoreBoundsPayload, ScoreRangePayload,
+	ScoredMembersPayload, ScriptPayload,
+};
+use crate::SharedState;
+
+/// Runs a single action given by a WebSocket or TCP client, blocking ones wait as
+/// long as they need to. Holds the transaction locks of the shards it works on
+/// meanwhile, in exclusive mode for scripts and in shared mode for anything else,
+/// except while a blocking action is waiting.
+pub async fn execute(
+	state: Arc<SharedState>,
+	action: Actions,
+	data: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+	match action {
+		Actions::BLPOP => match serde_json::from_value::<BlockingPopPayload>(data) {
+			Ok(data) => super::v1::blpop::handle_ws(state, data.keys, data.timeout).await,
+			Err(_) => invalid_data(),
+		},
+		Actions::EVAL | Actions::EVALSHA => {
+			let _transaction = state.transaction(&scope(&action, &data)).await;
+			run(state.clone(), action, data)
+		}
+		_ => {
+			let _operation = state.operation(&scope(&action, &data)).await;
+			run(state.clone(), action, data)
+		}
+	}
+}
+
+/// Keys `action` works on, taken from the `key`, `keys` and `items` of its payload.
+/// A script works on the keys it is given.
+pub fn scope(action: &Actions, data: &serde_json::Value) -> Scope {
+	match action {
+		Actions::LIST
+		| Actions::EXPIRING
+		| Actions::SAVE
+		| Actions::COMPACT
+		| Actions::CLEAN
+		| Actions::FLUSH => Scope::All,
+		_ => {
+			let key = data.get("key").and_then(serde_json::Value::as_str);
+			let keys = data
+				.get("keys")
+				.and_then(serde_json::Value::as_array)
+				.into_iter()
+				.flatten()
+				.filter_map(serde_json::Value::as_str);
+			let items = data
+				.get("items")
+				.and_then(serde_json::Value::as_array)
+				.into_iter()
+				.flatten()
+				.filter_map(|item| item.get("key")?.as_str());
+			Scope::Keys(
+				key
+					.into_iter()
+					.chain(keys)
+					.chain(items)
+					.map(String::from)
+					.collect(),
+			)
+		}
+	}
+}
+
+/// Runs `action` right away, the caller takes care of the transaction lock.
+/// Blocking actions do not wait, and transaction and subscription actions,
+/// which belong to the session, are refused.
+pub fn run(
+	state: Arc<SharedState>,
+	action: Actions,
+	data: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+	match action {
+		Actions::MULTI
+		| Actions::EXEC
+		| Actions::DISCARD
+		| Actions::WATCH
+		| Actions::UNWATCH
+		| Actions::SUBSCRIBE
+		| Actions::UNSUBSCRIBE
+		| Actions::PSUBSCRIBE
+		| Actions::PUNSUBSCRIBE => Err(Error::from_code(ErrorCode::InvalidTransaction)),
+		Actions::PING => super::v1::ping::handle_ws(),
+		Actions::STATS => super::v1::stats::handle_ws(state),
+		Actions::SAVE => super::v1::save::handle_ws(state),
+		Actions::COMPACT => super::v1::compact::handle_ws(state),
+		Actions::CLEAN => super::v1::clean::handle_ws(state),
+		Actions::FLUSH => super::v1::flush::handle_ws(state),
+		Actions::GET => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::get::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SET => {
+			if let Ok(data) = serde_json::from_value::<DataPayload>(data) {
+				super::v1::set::handle_ws(state, data.key, data.value, data.ttl)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::DEL => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::del::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::LIST => {
+			if let Ok(data) = serde_json::from_value::<ListPayload>(data) {
+				super::v1::list::handle_ws(state, data.prefix, data.limit, data.cursor)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::EXPIRING => {
+			if let Ok(data) = serde_json::from_value::<ExpiringPayload>(data) {
+				super::v1::expiring::handle_ws(state, data.seconds, data.limit)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::EXISTS => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::exists::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::INCR => {
+			if let Ok(data) = serde_json::from_value::<NumberDataPayload>(data) {
+				super::v1::incr::handle_ws(state, data.key, data.value, data.ttl, data.options)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::TTL => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::ttl::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::PTTL => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::pttl::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::EXPIRE => {
+			if let Ok(data) = serde_json::from_value::<ExpirePayload>(data) {
+				super::v1::expire::handle_ws(state, data.key, data.ttl)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::PEXPIRE => {
+			if let Ok(data) = serde_json::from_value::<ExpirePayload>(data) {
+				super::v1::pexpire::handle_ws(state, data.key, data.ttl)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::EXPIREAT => {
+			if let Ok(data) = serde_json::from_value::<ExpireAtPayload>(data) {
+				super::v1::expireat::handle_ws(state, data.key, data.timestamp)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::HSET => {
+			if let Ok(data) = serde_json::from_value::<HashSetPayload>(data) {
+				super::v1::hset::handle_ws(state, data.key, data.fields)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::HGET => {
+			if let Ok(data) = serde_json::from_value::<FieldPayload>(data) {
+				super::v1::hget::handle_ws(state, data.key, data.field)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::HDEL => {
+			if let Ok(data) = serde_json::from_value::<FieldsPayload>(data) {
+				super::v1::hdel::handle_ws(state, data.key, data.fields)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::HINCRBY => {
+			if let Ok(data) = serde_json::from_value::<FieldNumberPayload>(data) {
+				super::v1::hincrby::handle_ws(state, data.key, data.field, data.value)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::HEXISTS => {
+			if let Ok(data) = serde_json::from_value::<FieldPayload>(data) {
+				super::v1::hexists::handle_ws(state, data.key, data.field)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::HGETALL => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::hgetall::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::HLEN => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::hlen::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::HKEYS => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::hkeys::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::LPUSH => {
+			if let Ok(data) = serde_json::from_value::<PushPayload>(data) {
+				super::v1::lpush::handle_ws(state, data.key, data.values)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::RPUSH => {
+			if let Ok(data) = serde_json::from_value::<PushPayload>(data) {
+				super::v1::rpush::handle_ws(state, data.key, data.values)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::LPOP => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::lpop::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::RPOP => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::rpop::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::LRANGE => {
+			if let Ok(data) = serde_json::from_value::<RangePayload>(data) {
+				super::v1::lrange::handle_ws(state, data.key, data.start, data.stop)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::LLEN => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::llen::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::LTRIM => {
+			if let Ok(data) = serde_json::from_value::<RangePayload>(data) {
+				super::v1::ltrim::handle_ws(state, data.key, data.start, data.stop)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::LINDEX => {
+			if let Ok(data) = serde_json::from_value::<IndexPayload>(data) {
+				super::v1::lindex::handle_ws(state, data.key, data.index)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::BLPOP => {
+			if let Ok(data) = serde_json::from_value::<BlockingPopPayload>(data) {
+				super::v1::blpop::handle_now(state, data.keys)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SADD => {
+			if let Ok(data) = serde_json::from_value::<MembersPayload>(data) {
+				super::v1::sadd::handle_ws(state, data.key, data.members)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SREM => {
+			if let Ok(data) = serde_json::from_value::<MembersPayload>(data) {
+				super::v1::srem::handle_ws(state, data.key, data.members)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SISMEMBER => {
+			if let Ok(data) = serde_json::from_value::<MemberPayload>(data) {
+				super::v1::sismember::handle_ws(state, data.key, data.member)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SMEMBERS => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::smembers::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SCARD => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::scard::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SPOP => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::spop::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SRANDMEMBER => {
+			if let Ok(data) = serde_json::from_value::<CountPayload>(data) {
+				super::v1::srandmember::handle_ws(state, data.key, data.count)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SINTER => {
+			if let Ok(data) = serde_json::from_value::<KeysPayload>(data) {
+				super::v1::sinter::handle_ws(state, data.keys)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SUNION => {
+			if let Ok(data) = serde_json::from_value::<KeysPayload>(data) {
+				super::v1::sunion::handle_ws(state, data.keys)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SDIFF => {
+			if let Ok(data) = serde_json::from_value::<KeysPayload>(data) {
+				super::v1::sdiff::handle_ws(state, data.keys)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::ZADD => {
+			if let Ok(data) = serde_json::from_value::<ScoredMembersPayload>(data) {
+				super::v1::zadd::handle_ws(state, data.key, data.members)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::ZINCRBY => {
+			if let Ok(data) = serde_json::from_value::<MemberScorePayload>(data) {
+				super::v1::zincrby::handle_ws(state, data.key, data.member, data.value)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::ZSCORE => {
+			if let Ok(data) = serde_json::from_value::<MemberPayload>(data) {
+				super::v1::zscore::handle_ws(state, data.key, data.member)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::ZRANK => {
+			if let Ok(data) = serde_json::from_value::<MemberPayload>(data) {
+				super::v1::zrank::handle_ws(state, data.key, data.member)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::ZRANGE => {
+			if let Ok(data) = serde_json::from_value::<RangePayload>(data) {
+				super::v1::zrange::handle_ws(state, data.key, data.start, data.stop)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::ZRANGEBYSCORE => {
+			if let Ok(data) = serde_json::from_value::<ScoreRangePayload>(data) {
+				super::v1::zrangebyscore::handle_ws(
+					state,
+					data.key,
+					data.min,
+					data.max,
+					data.offset,
+					data.count,
+				)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::ZREM => {
+			if let Ok(data) = serde_json::from_value::<MembersPayload>(data) {
+				super::v1::zrem::handle_ws(state, data.key, data.members)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::ZREMRANGEBYSCORE => {
+			if let Ok(data) = serde_json::from_value::<ScoreBoundsPayload>(data) {
+				super::v1::zremrangebyscore::handle_ws(state, data.key, data.min, data.max)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::ZCARD => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::zcard::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::INCRBYFLOAT => {
+			if let Ok(data) = serde_json::from_value::<FloatDataPayload>(data) {
+				super::v1::incrbyfloat::handle_ws(state, data.key, data.value, data.ttl)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::DECRBYFLOAT => {
+			if let Ok(data) = serde_json::from_value::<FloatDataPayload>(data) {
+				super::v1::decrbyfloat::handle_ws(state, data.key, data.value, data.ttl)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SETNX => {
+			if let Ok(data) = serde_json::from_value::<DataPayload>(data) {
+				super::v1::setnx::handle_ws(state, data.key, data.value, data.ttl)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SETXX => {
+			if let Ok(data) = serde_json::from_value::<DataPayload>(data) {
+				super::v1::setxx::handle_ws(state, data.key, data.value, data.ttl)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::GETSET => {
+			if let Ok(data) = serde_json::from_value::<DataPayload>(data) {
+				super::v1::getset::handle_ws(state, data.key, data.value, data.ttl)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::CAS => {
+			if let Ok(data) = serde_json::from_value::<CasPayload>(data) {
+				super::v1::cas::handle_ws(
+					state,
+					data.key,
+					data.value,
+					data.ttl,
+					data.version,
+					data.expected,
+				)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::MGET => {
+			if let Ok(data) = serde_json::from_value::<KeysPayload>(data) {
+				super::v1::mget::handle_ws(state, data.keys)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::MSET => {
+			if let Ok(data) = serde_json::from_value::<MultiSetPayload>(data) {
+				super::v1::mset::handle_ws(state, data.items)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::MDEL => {
+			if let Ok(data) = serde_json::from_value::<KeysPayload>(data) {
+				super::v1::mdel::handle_ws(state, data.keys)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::MEXISTS => {
+			if let Ok(data) = serde_json::from_value::<KeysPayload>(data) {
+				super::v1::mexists::handle_ws(state, data.keys)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::EVAL => {
+			if let Ok(data) = serde_json::from_value::<EvalPayload>(data) {
+				super::v1::eval::handle_ws(state, data.script, data.keys, data.args)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::EVALSHA => {
+			if let Ok(data) = serde_json::from_value::<EvalShaPayload>(data) {
+				super::v1::evalsha::handle_ws(state, data.hash, data.keys, data.args)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::SCRIPTLOAD => {
+			if let Ok(data) = serde_json::from_value::<ScriptPayload>(data) {
+				super::v1::scriptload::handle_ws(state, data.script)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::PUBLISH => {
+			if let Ok(data) = serde_json::from_value::<PublishPayload>(data) {
+				super::v1::publish::handle_ws(state, data.channel, data.message)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::PERSIST => {
+			if let Ok(data) = serde_json::from_value::<KeyPayload>(data) {
+				super::v1::persist::handle_ws(state, data.key)
+			} else {
+				invalid_data()
+			}
+		}
+		Actions::DECR => {
+			if let Ok(data) = serde_json::from_value::<NumberDataPayload>(data) {
+				super::v1::decr::handle_ws(state, data.key, data.value, data.ttl, data.options)
+			} else {
+				invalid_data()
+			}
+		}
+	}
+}
+
+pub fn invalid_data() -> Result<serde_json::Value, Error> {
+	Err(Error::from_code(ErrorCode::InvalidData))
+}
@@ -2,9 +2,9 @@ use axum::{extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
 use std::sync::atomic::Ordering;
-use std::sync::{Arc, MutexGuard};
+use std::sync::Arc;
 
-use crate::caches::cache::Cache;
+use crate::caches::stats::{SaveStatus, Stats};
 use crate::error::{Error, ErrorCode};
 use crate::SharedState;
 
@@ -16,7 +16,7 @@ pub async fn handle_get(
 		return Json(Error::from_code(ErrorCode::Success)).into_response();
 	}
 
-	let shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
+	let stats: Stats = state.cache.stats();
 
 	let response_body: String = format!(
 		"# HELP cache_writes Total cache writes\n\
@@ -31,18 +31,46 @@ pub async fn handle_get(
 		 # HELP cache_lists Total cache lists\n\
 		 # TYPE cache_lists counter\n\
 		 cache_lists {}\n\
+		 # HELP cache_expired Total keys removed because their TTL passed\n\
+		 # TYPE cache_expired counter\n\
+		 cache_expired {}\n\
+		 # HELP cache_evicted Total keys removed to stay within the memory or key limit\n\
+		 # TYPE cache_evicted counter\n\
+		 cache_evicted {}\n\
+		 # HELP cache_changes_since_save Writes and deletes not yet saved to a snapshot\n\
+		 # TYPE cache_changes_since_save gauge\n\
+		 cache_changes_since_save {}\n\
+		 # HELP cache_last_save_time Unix time in seconds of the last successful snapshot\n\
+		 # TYPE cache_last_save_time gauge\n\
+		 cache_last_save_time {}\n\
+		 # HELP cache_last_save_duration_seconds Duration of the last snapshot\n\
+		 # TYPE cache_last_save_duration_seconds gauge\n\
+		 cache_last_save_duration_seconds {}\n\
+		 # HELP cache_last_save_success Whether the last snapshot succeeded, 0 until one was saved\n\
+		 # TYPE cache_last_save_success gauge\n\
+		 cache_last_save_success {}\n\
 		 # HELP cache_keys Number of keys in a cache\n\
 		 # TYPE cache_keys gauge\n\
 		 cache_keys {}\n\
+		 # HELP cache_used_memory Estimated bytes used by keys and values\n\
+		 # TYPE cache_used_memory gauge\n\
+		 cache_used_memory {}\n\
 		 # HELP ws_connections Number of open WebSocket connections\n\
 		 # TYPE ws_connections gauge\n\
 		 ws_connections {}\n\
 		 # EOF",
-		shared_cache.stats.writes,
-		shared_cache.stats.reads,
-		shared_cache.stats.deletes,
-		shared_cache.stats.lists,
-		shared_cache.cache.len(),
+		stats.writes,
+		stats.reads,
+		stats.deletes,
+		stats.lists,
+		stats.expired,
+		stats.evicted,
+		state.cache.changes_since_save(),
+		stats.last_save_time / 1000,
+		stats.last_save_duration as f64 / 1000.0,
+		(stats.last_save_status == SaveStatus::Ok) as u8,
+		state.cache.key_count(),
+		state.cache.used_memory(),
 		state.ws_connections.load(Ordering::Acquire)
 	);
 
@@ -0,0 +1,363 @@
+use axum::body::Body;
+use axum::extract::{RawPathParams, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::RequestExt;
+use std::sync::Arc;
+
+use super::dispatch;
+use super::ws::WsResponse;
+use crate::caches::cache::{Shard, ShardsReadGuard};
+use crate::error::{Error, ErrorCode};
+use crate::state::Scope;
+use crate::types::{Actions, KeysPayload, QueuedAction, WatchedKey};
+use crate::SharedState;
+
+/// Transaction state of a WebSocket or TCP session.
+///
+/// MULTI opens a transaction, the actions that follow are queued until EXEC runs
+/// them all at once or DISCARD drops them. Keys given to WATCH before that make
+/// EXEC run nothing when any of them changed in the meantime.
+#[derive(Debug, Default)]
+pub struct Transaction {
+	/// Watched keys with the versions they had when WATCH was called.
+	watched: Vec<WatchedKey>,
+	/// Actions queued since MULTI, `None` when no transaction is open.
+	queued: Option<Vec<QueuedAction>>,
+}
+
+impl Transaction {
+	/// Handles an action of the session. Transaction actions are handled here,
+	/// others are queued while a transaction is open and run right away otherwise.
+	pub async fn execute(
+		&mut self,
+		state: Arc<SharedState>,
+		id: u64,
+		action: Actions,
+		data: serde_json::Value,
+	) -> Result<serde_json::Value, Error> {
+		match action {
+			Actions::MULTI => {
+				if self.queued.is_some() {
+					return Err(Error::from_code(ErrorCode::InvalidTransaction));
+				}
+				self.queued = Some(Vec::new());
+				Ok(serde_json::Value::Null)
+			}
+			Actions::EXEC => {
+				let Some(queued) = self.queued.take() else {
+					return Err(Error::from_code(ErrorCode::InvalidTransaction));
+				};
+				let watched: Vec<WatchedKey> = std::mem::take(&mut self.watched);
+				exec(state, &watched, queued).await
+			}
+			Actions::DISCARD => {
+				if self.queued.take().is_none() {
+					return Err(Error::from_code(ErrorCode::InvalidTransaction));
+				}
+				self.watched.clear();
+				Ok(serde_json::Value::Null)
+			}
+			Actions::WATCH => {
+				if self.queued.is_some() {
+					return Err(Error::from_code(ErrorCode::InvalidTransaction));
+				}
+				let Ok(data) = serde_json::from_value::<KeysPayload>(data) else {
+					return dispatch::invalid_data();
+				};
+				let _operation = state.operation(&Scope::Keys(data.keys.clone())).await;
+				let shards: ShardsReadGuard = state.cache.read_many(&data.keys);
+				self.watched.extend(data.keys.iter().map(|key| {
+					let shard: &Shard = shards.shard(key);
+					WatchedKey {
+						key: key.clone(),
+						version: shard.get(key).map_or(0, |item| item.version),
+						removals: Some(shard.removals),
+					}
+				}));
+				Ok(serde_json::Value::Null)
+			}
+			Actions::UNWATCH => {
+				self.watched.clear();
+				Ok(serde_json::Value::Null)
+			}
+			_ => match &mut self.queued {
+				Some(queued) => {
+					queued.push(QueuedAction { id, action, data });
+					Ok(serde_json::Value::from("QUEUED"))
+				}
+				None => dispatch::execute(state, action, data).await,
+			},
+		}
+	}
+}
+
+/// Runs `actions` one after another while no other operation can run on the
+/// shards they and the `watched` keys are in, and returns their results in order,
+/// each in a `WsResponse` with the id it was queued with. An action that fails
+/// does not stop the others. Nothing runs when any of the `watched` keys changed.
+pub async fn exec(
+	state: Arc<SharedState>,
+	watched: &[WatchedKey],
+	actions: Vec<QueuedAction>,
+) -> Result<serde_json::Value, Error> {
+	let _transaction = state.transaction(&scope(watched, &actions)).await;
+	if changed(&state, watched) {
+		return Err(Error::from_code(ErrorCode::Conflict));
+	}
+
+	let responses: Vec<WsResponse> = actions
+		.into_iter()
+		.map(|queued| {
+			let result = dispatch::run(state.clone(), queued.action, queued.data);
+			WsResponse::new(queued.id, result)
+		})
+		.collect();
+	Ok(serde_json::to_value(responses).unwrap())
+}
+
+/// Runs an HTTP request as a single operation, see `SharedState::operation`.
+/// The body is read first, so a slow client cannot hold up transactions.
+///
+/// The action is named by the path, `/v1/<action>/...`, and its keys are found
+/// in the JSON body or the `{key}` path parameter like in `dispatch::scope`.
+/// Other requests do not touch the cache and run without a lock.
+pub async fn isolate(
+	State(state): State<Arc<SharedState>>,
+	params: RawPathParams,
+	request: Request,
+	next: Next,
+) -> Response {
+	let (parts, body) = request.with_limited_body().into_parts();
+	let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+		return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+	};
+
+	let action: Option<Actions> = parts
+		.uri
+		.path()
+		.strip_prefix("/v1/")
+		.map(|path| path.split('/').next().unwrap_or_default().to_uppercase())
+		.and_then(|name| serde_json::from_value(serde_json::Value::from(name)).ok());
+	let scope: Scope = match action {
+		Some(action) => {
+			let mut data: serde_json::Value =
+				serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+			if let Some((_, key)) = params.iter().find(|(name, _)| *name == "key") {
+				data = serde_json::json!({ "key": key });
+			}
+			dispatch::scope(&action, &data)
+		}
+		None => Scope::Keys(Vec::new()),
+	};
+
+	let _operation = state.operation(&scope).await;
+	next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// Everything a transaction works on, the whole cache when one of its actions does.
+fn scope(watched: &[WatchedKey], actions: &[QueuedAction]) -> Scope {
+	let mut keys: Vec<String> = watched.iter().map(|watched| watched.key.clone()).collect();
+	for queued in actions {
+		match dispatch::scope(&queued.action, &queued.data) {
+			Scope::Keys(action_keys) => keys.extend(action_keys),
+			Scope::All => return Scope::All,
+		}
+	}
+	Scope::Keys(keys)
+}
+
+fn changed(state: &SharedState, watched: &[WatchedKey]) -> bool {
+	let keys: Vec<String> = watched.iter().map(|watched| watched.key.clone()).collect();
+	let shards: ShardsReadGuard = state.cache.read_many(&keys);
+	watched
+		.iter()
+		.any(|watched| !unchanged(shards.shard(&watched.key), watched))
+}
+
+/// Whether `watched` still has the version it was seen at. A key that is missing
+/// again also changed when keys were removed from its shard since, as it may have
+/// been stored and removed in between.
+fn unchanged(shard: &Shard, watched: &WatchedKey) -> bool {
+	match shard.get(&watched.key) {
+		Some(item) => item.version == watched.version,
+		None => {
+			watched.version == 0
+				&& watched
+					.removals
+					.is_none_or(|removals| removals == shard.removals)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::cache::Cache;
+	use crate::caches::data::Data;
+	use crate::caches::eviction::Limits;
+	use crate::caches::snapshot::SnapshotOptions;
+	use serde_json::json;
+
+	fn state() -> Arc<SharedState> {
+		SharedState::for_test(Cache::new(
+			String::new(),
+			false,
+			SnapshotOptions::default(),
+			Limits::default(),
+			4,
+		))
+	}
+
+	async fn send(
+		transaction: &mut Transaction,
+		state: &Arc<SharedState>,
+		action: Actions,
+		data: serde_json::Value,
+	) -> serde_json::Value {
+		match transaction.execute(state.clone(), 1, action, data).await {
+			Ok(result) => result,
+			Err(error) => serde_json::to_value(error).unwrap(),
+		}
+	}
+
+	fn set(state: &SharedState, key: &str, value: i64) {
+		state
+			.cache
+			.write(key)
+			.set(key.to_string(), json!(value), 0)
+			.unwrap();
+	}
+
+	fn value(state: &SharedState, key: &str) -> Option<serde_json::Value> {
+		match &state.cache.read(key).get(key)?.value {
+			Data::Value(value) => Some(value.clone()),
+			_ => None,
+		}
+	}
+
+	#[tokio::test]
+	async fn runs_queued_actions_on_exec() {
+		let state: Arc<SharedState> = state();
+		let mut transaction: Transaction = Transaction::default();
+		send(&mut transaction, &state, Actions::MULTI, json!(null)).await;
+		for key in ["a", "b"] {
+			let queued = send(
+				&mut transaction,
+				&state,
+				Actions::SET,
+				json!({ "key": key, "value": 1, "ttl": 0 }),
+			)
+			.await;
+			assert_eq!(queued, json!("QUEUED"));
+		}
+		assert_eq!(value(&state, "a"), None);
+
+		let results = send(&mut transaction, &state, Actions::EXEC, json!(null)).await;
+		assert_eq!(results.as_array().map(Vec::len), Some(2));
+		assert_eq!(value(&state, "a"), Some(json!(1)));
+		assert_eq!(value(&state, "b"), Some(json!(1)));
+	}
+
+	#[tokio::test]
+	async fn runs_nothing_when_a_watched_key_changed() {
+		let state: Arc<SharedState> = state();
+		set(&state, "a", 1);
+		let mut transaction: Transaction = Transaction::default();
+		send(
+			&mut transaction,
+			&state,
+			Actions::WATCH,
+			json!({ "keys": ["a", "b"] }),
+		)
+		.await;
+		send(&mut transaction, &state, Actions::MULTI, json!(null)).await;
+		send(
+			&mut transaction,
+			&state,
+			Actions::SET,
+			json!({ "key": "a", "value": 3, "ttl": 0 }),
+		)
+		.await;
+
+		set(&state, "a", 2);
+		let result = send(&mut transaction, &state, Actions::EXEC, json!(null)).await;
+		assert_eq!(result["code"], ErrorCode::Conflict as u64);
+		assert_eq!(value(&state, "a"), Some(json!(2)));
+
+		// A key that did not exist when it was watched counts as changed once set.
+		send(
+			&mut transaction,
+			&state,
+			Actions::WATCH,
+			json!({ "keys": ["b"] }),
+		)
+		.await;
+		send(&mut transaction, &state, Actions::MULTI, json!(null)).await;
+		set(&state, "b", 1);
+		let result = send(&mut transaction, &state, Actions::EXEC, json!(null)).await;
+		assert_eq!(result["code"], ErrorCode::Conflict as u64);
+	}
+
+	#[tokio::test]
+	async fn catches_watched_keys_that_came_and_went() {
+		let state: Arc<SharedState> = state();
+		let mut transaction: Transaction = Transaction::default();
+		send(
+			&mut transaction,
+			&state,
+			Actions::WATCH,
+			json!({ "keys": ["a"] }),
+		)
+		.await;
+		send(&mut transaction, &state, Actions::MULTI, json!(null)).await;
+
+		set(&state, "a", 1);
+		state.cache.write("a").delete("a");
+		let result = send(&mut transaction, &state, Actions::EXEC, json!(null)).await;
+		assert_eq!(result["code"], ErrorCode::Conflict as u64);
+	}
+
+	#[tokio::test]
+	async fn runs_when_watched_keys_are_unchanged() {
+		let state: Arc<SharedState> = state();
+		set(&state, "a", 1);
+		let mut transaction: Transaction = Transaction::default();
+		send(
+			&mut transaction,
+			&state,
+			Actions::WATCH,
+			json!({ "keys": ["a", "b"] }),
+		)
+		.await;
+		send(&mut transaction, &state, Actions::MULTI, json!(null)).await;
+		send(
+			&mut transaction,
+			&state,
+			Actions::SET,
+			json!({ "key": "a", "value": 2, "ttl": 0 }),
+		)
+		.await;
+
+		let results = send(&mut transaction, &state, Actions::EXEC, json!(null)).await;
+		assert!(results.is_array());
+		assert_eq!(value(&state, "a"), Some(json!(2)));
+	}
+
+	#[tokio::test]
+	async fn rejects_transaction_actions_out_of_place() {
+		let state: Arc<SharedState> = state();
+		let mut transaction: Transaction = Transaction::default();
+		for action in [Actions::EXEC, Actions::DISCARD] {
+			let result = send(&mut transaction, &state, action, json!(null)).await;
+			assert_eq!(result["code"], ErrorCode::InvalidTransaction as u64);
+		}
+
+		send(&mut transaction, &state, Actions::MULTI, json!(null)).await;
+		for action in [Actions::MULTI, Actions::WATCH] {
+			let result = send(&mut transaction, &state, action, json!({ "keys": ["a"] })).await;
+			assert_eq!(result["code"], ErrorCode::InvalidTransaction as u64);
+		}
+	}
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::futures::Notified;
+use tokio::time::{Duration, Instant};
+
+use crate::caches::cache::ShardWriteGuard;
+use crate::caches::list::End;
+use crate::error::{Error, ErrorCode};
+use crate::state::Scope;
+use crate::SharedState;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PoppedItem {
+	pub key: String,
+	pub value: serde_json::Value,
+}
+
+/// Pops the first element of the first non-empty list among `keys`.
+fn pop_first(state: &SharedState, keys: &[String]) -> Result<Option<PoppedItem>, ErrorCode> {
+	for key in keys {
+		let mut shard: ShardWriteGuard = state.cache.write(key);
+		if let Some(value) = shard.pop(key, End::Front)? {
+			return Ok(Some(PoppedItem {
+				key: key.clone(),
+				value,
+			}));
+		}
+	}
+	Ok(None)
+}
+
+/// Pops like `handle_ws` without waiting, for transactions where nothing else
+/// could push meanwhile. Returns null when all lists are empty.
+pub fn handle_now(state: Arc<SharedState>, keys: Vec<String>) -> Result<serde_json::Value, Error> {
+	match pop_first(&state, &keys) {
+		Ok(Some(item)) => Ok(serde_json::to_value(item).unwrap()),
+		Ok(None) => Ok(serde_json::Value::Null),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+/// Waits up to `timeout` seconds, or forever when it is 0, until one of the
+/// lists has an element and pops it. Only available to WebSocket and TCP
+/// clients, which stay blocked meanwhile. Returns null on timeout.
+pub async fn handle_ws(
+	state: Arc<SharedState>,
+	keys: Vec<String>,
+	timeout: u64,
+) -> Result<serde_json::Value, Error> {
+	let deadline: Option<Instant> =
+		(timeout > 0).then(|| Instant::now() + Duration::from_secs(timeout));
+
+	loop {
+		let pushed: Notified = state.cache.pushed();
+		let popped: Result<Option<PoppedItem>, ErrorCode> = {
+			let _operation = state.operation(&Scope::Keys(keys.clone())).await;
+			pop_first(&state, &keys)
+		};
+		match popped {
+			Ok(Some(item)) => return Ok(serde_json::to_value(item).unwrap()),
+			Ok(None) => {}
+			Err(code) => return Err(Error::from_code(code)),
+		}
+
+		tokio::select! {
+			_ = pushed => {}
+			_ = async {
+				match deadline {
+					Some(deadline) => tokio::time::sleep_until(deadline).await,
+					None => std::future::pending().await,
+				}
+			} => return Ok(serde_json::Value::Null),
+			_ = state.shutdown_requested() => return Ok(serde_json::Value::Null),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::cache::Cache;
+	use crate::caches::eviction::Limits;
+	use crate::caches::snapshot::SnapshotOptions;
+	use serde_json::json;
+
+	fn state() -> Arc<SharedState> {
+		SharedState::for_test(Cache::new(
+			String::new(),
+			false,
+			SnapshotOptions::default(),
+			Limits::default(),
+			4,
+		))
+	}
+
+	fn push(state: &SharedState, key: &str, value: serde_json::Value) {
+		state
+			.cache
+			.write(key)
+			.push(key, End::Back, vec![value])
+			.unwrap();
+	}
+
+	fn keys() -> Vec<String> {
+		vec!["a".to_string(), "b".to_string()]
+	}
+
+	#[tokio::test]
+	async fn pops_from_the_first_list_with_elements() {
+		let state: Arc<SharedState> = state();
+		push(&state, "b", json!(1));
+		push(&state, "b", json!(2));
+
+		let popped = handle_ws(state.clone(), keys(), 1).await.unwrap();
+		assert_eq!(popped, json!({ "key": "b", "value": 1 }));
+	}
+
+	#[tokio::test]
+	async fn waits_for_a_push() {
+		let state: Arc<SharedState> = state();
+		let waiting = tokio::spawn(handle_ws(state.clone(), keys(), 0));
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		assert!(!waiting.is_finished());
+
+		push(&state, "a", json!("x"));
+		let popped = waiting.await.unwrap().unwrap();
+		assert_eq!(popped, json!({ "key": "a", "value": "x" }));
+	}
+
+	#[tokio::test]
+	async fn returns_null_on_timeout() {
+		let popped = handle_ws(state(), keys(), 1).await.unwrap();
+		assert_eq!(popped, serde_json::Value::Null);
+	}
+}
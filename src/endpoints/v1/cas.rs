@@ -0,0 +1,196 @@
+use axum::body::Body;
+use axum::http::{Response, StatusCode};
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization, ETag};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::caches::cache::{CacheItem, ShardWriteGuard};
+use crate::caches::data::Data;
+use crate::error::{Error, ErrorCode};
+use crate::types::CasPayload;
+use crate::SharedState;
+
+/// Strong entity tag of an item version, used by the HTTP endpoints.
+pub fn etag(version: u64) -> ETag {
+	format!("\"{}\"", version).parse().unwrap()
+}
+
+/// Stores `value` only when the current version is `version` and the current
+/// value is `expected`, checking only the ones given. Version 0 stands for a
+/// missing key. Returns the new version.
+pub fn compare_and_set(
+	shard: &mut ShardWriteGuard,
+	key: String,
+	value: Value,
+	ttl: u64,
+	version: Option<u64>,
+	expected: Option<Value>,
+) -> Result<u64, ErrorCode> {
+	if version.is_none() && expected.is_none() {
+		return Err(ErrorCode::InvalidData);
+	}
+
+	let current: Option<&CacheItem> = shard.get(&key);
+	let version_matches: bool =
+		version.is_none_or(|version| current.map_or(0, |item| item.version) == version);
+	let value_matches: bool = expected.is_none_or(|expected| {
+		current.is_some_and(|item| matches!(&item.value, Data::Value(value) if *value == expected))
+	});
+	if !version_matches || !value_matches {
+		return Err(ErrorCode::Conflict);
+	}
+	shard.set(key, value, 1000 * ttl as u128)
+}
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	value: Value,
+	ttl: u64,
+	version: Option<u64>,
+	expected: Option<Value>,
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match compare_and_set(&mut shard, key, value, ttl, version, expected) {
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+pub fn handle(
+	state: Arc<SharedState>,
+	key: String,
+	value: Value,
+	ttl: u64,
+	version: Option<u64>,
+	expected: Option<Value>,
+) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match compare_and_set(&mut shard, key, value, ttl, version, expected) {
+		Ok(result) => (TypedHeader(etag(result)), Json(result)).into_response(),
+		Err(ErrorCode::Conflict) => (
+			StatusCode::PRECONDITION_FAILED,
+			Json(Error::from_code(ErrorCode::Conflict)),
+		)
+			.into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<CasPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(
+		state,
+		payload.key,
+		payload.value,
+		payload.ttl,
+		payload.version,
+		payload.expected,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::cache::Cache;
+	use crate::caches::eviction::Limits;
+	use crate::caches::snapshot::SnapshotOptions;
+	use serde_json::json;
+
+	fn cache() -> Cache {
+		Cache::new(
+			String::new(),
+			false,
+			SnapshotOptions::default(),
+			Limits::default(),
+			4,
+		)
+	}
+
+	fn cas(
+		cache: &Cache,
+		value: Value,
+		version: Option<u64>,
+		expected: Option<Value>,
+	) -> Result<u64, ErrorCode> {
+		let mut shard: ShardWriteGuard = cache.write("key");
+		compare_and_set(&mut shard, "key".to_string(), value, 0, version, expected)
+	}
+
+	#[test]
+	fn hands_out_a_new_version_on_every_write() {
+		let cache: Cache = cache();
+		let first: u64 = cache
+			.write("key")
+			.set("key".to_string(), json!(1), 0)
+			.unwrap();
+		let second: u64 = cache
+			.write("key")
+			.set("key".to_string(), json!(1), 0)
+			.unwrap();
+		assert!(second > first);
+		assert_eq!(cache.read("key").get("key").unwrap().version, second);
+	}
+
+	#[test]
+	fn creates_missing_keys_at_version_zero() {
+		let cache: Cache = cache();
+		let version: u64 = cas(&cache, json!(1), Some(0), None).unwrap();
+		assert!(version > 0);
+		assert!(matches!(
+			cas(&cache, json!(2), Some(0), None),
+			Err(ErrorCode::Conflict)
+		));
+	}
+
+	#[test]
+	fn sets_only_the_expected_version() {
+		let cache: Cache = cache();
+		let version: u64 = cache
+			.write("key")
+			.set("key".to_string(), json!(1), 0)
+			.unwrap();
+
+		assert!(matches!(
+			cas(&cache, json!(2), Some(version + 1), None),
+			Err(ErrorCode::Conflict)
+		));
+		let next: u64 = cas(&cache, json!(2), Some(version), None).unwrap();
+		assert!(next > version);
+		// The old version is gone with the write that replaced it.
+		assert!(matches!(
+			cas(&cache, json!(3), Some(version), None),
+			Err(ErrorCode::Conflict)
+		));
+	}
+
+	#[test]
+	fn sets_only_the_expected_value() {
+		let cache: Cache = cache();
+		cache
+			.write("key")
+			.set("key".to_string(), json!("a"), 0)
+			.unwrap();
+
+		assert!(matches!(
+			cas(&cache, json!("c"), None, Some(json!("b"))),
+			Err(ErrorCode::Conflict)
+		));
+		assert!(cas(&cache, json!("b"), None, Some(json!("a"))).is_ok());
+		assert!(matches!(
+			cas(&cache, json!("c"), None, None),
+			Err(ErrorCode::InvalidData)
+		));
+	}
+}
@@ -3,22 +3,19 @@ use axum::http::Response;
 use axum::{extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
-use std::sync::{Arc, MutexGuard};
+use std::sync::Arc;
 
-use crate::caches::cache::Cache;
 use crate::error::{Error, ErrorCode};
 use crate::SharedState;
 
-pub fn handle_ws(state: Arc<SharedState>) -> serde_json::Value {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-	shared_cache.clean();
+pub fn handle_ws(state: Arc<SharedState>) -> Result<serde_json::Value, Error> {
+	state.cache.clean();
 
-	serde_json::to_value(Error::from_code(ErrorCode::Success)).unwrap()
+	Ok(serde_json::Value::Null)
 }
 
 pub fn handle(state: Arc<SharedState>) -> Response<Body> {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-	shared_cache.clean();
+	state.cache.clean();
 
 	Json(Error::from_code(ErrorCode::Success)).into_response()
 }
@@ -0,0 +1,35 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::error::{Error, ErrorCode};
+use crate::tasks::start_compaction;
+use crate::SharedState;
+
+pub fn handle_ws(state: Arc<SharedState>) -> Result<serde_json::Value, Error> {
+	match start_compaction(state) {
+		true => Ok(serde_json::Value::Null),
+		false => Err(Error::from_code(ErrorCode::CompactionInProgress)),
+	}
+}
+
+pub fn handle(state: Arc<SharedState>) -> Response<Body> {
+	match start_compaction(state) {
+		true => Json(Error::from_code(ErrorCode::Success)).into_response(),
+		false => Json(Error::from_code(ErrorCode::CompactionInProgress)).into_response(),
+	}
+}
+
+pub async fn handle_get(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state)
+}
@@ -3,93 +3,50 @@ use axum::http::Response;
 use axum::{extract::Path, extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
-use serde_json::Value;
-use std::sync::{Arc, MutexGuard};
+use std::sync::Arc;
 
-use crate::caches::cache::Cache;
+use super::incr::increment;
+use crate::caches::cache::ShardWriteGuard;
 use crate::error::{Error, ErrorCode};
-use crate::types::NumberDataPayload;
-use crate::utils::current_time;
+use crate::types::{CounterOptions, NumberDataPayload};
 use crate::SharedState;
 
-pub fn handle_ws(state: Arc<SharedState>, key: String, value: i64, ttl: u64) -> serde_json::Value {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	value: i64,
+	ttl: u64,
+	options: CounterOptions,
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
 
-	let new_value: i64 = match shared_cache.get(&key) {
-		Some(item) => {
-			if let Value::Number(n) = &item.value {
-				if let Some(i) = n.as_i64() {
-					match i.checked_sub(value) {
-						Some(result) => result,
-						None => {
-							return serde_json::to_value(Error::from_code(ErrorCode::IntegerOverflow)).unwrap();
-						}
-					}
-				} else {
-					return serde_json::to_value(Error::from_code(ErrorCode::InvalidInteger)).unwrap();
-				}
-			} else {
-				return serde_json::to_value(Error::from_code(ErrorCode::InvalidNumber)).unwrap();
-			}
-		}
-		None => 1,
-	};
-
-	let new_ttl: u128 = match shared_cache.get(&key) {
-		Some(item) => {
-			let current_time: u128 = current_time();
-			if item.expiration > current_time {
-				item.expiration - current_time
-			} else {
-				0
-			}
-		}
-		None => 1000 * ttl as u128,
-	};
-
-	shared_cache.set(key.clone(), Value::Number(new_value.into()), new_ttl);
-
-	serde_json::to_value(Error::from_code(ErrorCode::Success)).unwrap()
+	match value
+		.checked_neg()
+		.ok_or(ErrorCode::IntegerOverflow)
+		.and_then(|delta| increment(&mut shard, key, delta, ttl, options))
+	{
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
 }
 
-pub fn handle(state: Arc<SharedState>, key: String, value: i64, ttl: u64) -> Response<Body> {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
+pub fn handle(
+	state: Arc<SharedState>,
+	key: String,
+	value: i64,
+	ttl: u64,
+	options: CounterOptions,
+) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
 
-	let new_value: i64 = match shared_cache.get(&key) {
-		Some(item) => {
-			if let Value::Number(n) = &item.value {
-				if let Some(i) = n.as_i64() {
-					match i.checked_sub(value) {
-						Some(result) => result,
-						None => {
-							return Json(Error::from_code(ErrorCode::IntegerOverflow)).into_response();
-						}
-					}
-				} else {
-					return Json(Error::from_code(ErrorCode::InvalidInteger)).into_response();
-				}
-			} else {
-				return Json(Error::from_code(ErrorCode::InvalidNumber)).into_response();
-			}
-		}
-		None => 1,
-	};
-
-	let new_ttl: u128 = match shared_cache.get(&key) {
-		Some(item) => {
-			let current_time: u128 = current_time();
-			if item.expiration > current_time {
-				item.expiration - current_time
-			} else {
-				0
-			}
-		}
-		None => 1000 * ttl as u128,
-	};
-
-	shared_cache.set(key.clone(), Value::Number(new_value.into()), new_ttl);
-
-	Json(Error::from_code(ErrorCode::Success)).into_response()
+	match value
+		.checked_neg()
+		.ok_or(ErrorCode::IntegerOverflow)
+		.and_then(|delta| increment(&mut shard, key, delta, ttl, options))
+	{
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
 }
 
 pub async fn handle_get(
@@ -101,7 +58,7 @@ pub async fn handle_get(
 		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
 	}
 
-	handle(state, key, value, ttl)
+	handle(state, key, value, ttl, CounterOptions::default())
 }
 
 pub async fn handle_post(
@@ -113,5 +70,11 @@ pub async fn handle_post(
 		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
 	}
 
-	handle(state, payload.key, payload.value, payload.ttl)
+	handle(
+		state,
+		payload.key,
+		payload.value,
+		payload.ttl,
+		payload.options,
+	)
 }
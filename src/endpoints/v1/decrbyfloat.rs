@@ -0,0 +1,59 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use super::incrbyfloat::increment_float;
+use crate::caches::cache::ShardWriteGuard;
+use crate::error::{Error, ErrorCode};
+use crate::types::FloatDataPayload;
+use crate::SharedState;
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	value: f64,
+	ttl: u64,
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match increment_float(&mut shard, key, -value, ttl) {
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+pub fn handle(state: Arc<SharedState>, key: String, value: f64, ttl: u64) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match increment_float(&mut shard, key, -value, ttl) {
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
+}
+
+pub async fn handle_get(
+	Path((key, value, ttl)): Path<(String, f64, u64)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key, value, ttl)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<FloatDataPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key, payload.value, payload.ttl)
+}
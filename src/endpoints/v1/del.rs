@@ -3,23 +3,23 @@ use axum::http::Response;
 use axum::{extract::Path, extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
-use std::sync::{Arc, MutexGuard};
+use std::sync::Arc;
 
-use crate::caches::cache::Cache;
+use crate::caches::cache::ShardWriteGuard;
 use crate::error::{Error, ErrorCode};
 use crate::types::KeyPayload;
 use crate::SharedState;
 
-pub fn handle_ws(state: Arc<SharedState>, key: String) -> serde_json::Value {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-	shared_cache.delete(&key);
+pub fn handle_ws(state: Arc<SharedState>, key: String) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+	shard.delete(&key);
 
-	serde_json::to_value(Error::from_code(ErrorCode::Success)).unwrap()
+	Ok(serde_json::Value::Null)
 }
 
 pub fn handle(state: Arc<SharedState>, key: String) -> Response<Body> {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-	shared_cache.delete(&key);
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+	shard.delete(&key);
 
 	Json(Error::from_code(ErrorCode::Success)).into_response()
 }
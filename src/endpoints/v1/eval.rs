@@ -0,0 +1,58 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::error::{Error, ErrorCode};
+use crate::scripts;
+use crate::state::Scope;
+use crate::types::EvalPayload;
+use crate::SharedState;
+
+/// Loads `script` like SCRIPTLOAD and runs it, see `scripts::run`.
+/// The caller must hold the transaction locks of the shards of `keys` in exclusive mode.
+pub fn eval(
+	state: Arc<SharedState>,
+	script: &str,
+	keys: Vec<String>,
+	args: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, Error> {
+	let (_, script) = state.scripts.load(script)?;
+	scripts::run(state, &script, keys, args)
+}
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	script: String,
+	keys: Vec<String>,
+	args: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, Error> {
+	eval(state, &script, keys, args)
+}
+
+pub fn handle(
+	state: Arc<SharedState>,
+	script: String,
+	keys: Vec<String>,
+	args: Vec<serde_json::Value>,
+) -> Response<Body> {
+	match eval(state, &script, keys, args) {
+		Ok(result) => Json(result).into_response(),
+		Err(error) => Json(error).into_response(),
+	}
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<EvalPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	let _transaction = state.transaction(&Scope::Keys(payload.keys.clone())).await;
+	handle(state.clone(), payload.script, payload.keys, payload.args)
+}
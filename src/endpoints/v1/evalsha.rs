@@ -0,0 +1,60 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::error::{Error, ErrorCode};
+use crate::scripts;
+use crate::state::Scope;
+use crate::types::EvalShaPayload;
+use crate::SharedState;
+
+/// Runs the script loaded under `hash`, see `scripts::run`.
+/// The caller must hold the transaction locks of the shards of `keys` in exclusive mode.
+pub fn eval_sha(
+	state: Arc<SharedState>,
+	hash: &str,
+	keys: Vec<String>,
+	args: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, Error> {
+	let Some(script) = state.scripts.get(hash) else {
+		return Err(Error::from_code(ErrorCode::UnknownScript));
+	};
+	scripts::run(state, &script, keys, args)
+}
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	hash: String,
+	keys: Vec<String>,
+	args: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, Error> {
+	eval_sha(state, &hash, keys, args)
+}
+
+pub fn handle(
+	state: Arc<SharedState>,
+	hash: String,
+	keys: Vec<String>,
+	args: Vec<serde_json::Value>,
+) -> Response<Body> {
+	match eval_sha(state, &hash, keys, args) {
+		Ok(result) => Json(result).into_response(),
+		Err(error) => Json(error).into_response(),
+	}
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<EvalShaPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	let _transaction = state.transaction(&Scope::Keys(payload.keys.clone())).await;
+	handle(state.clone(), payload.hash, payload.keys, payload.args)
+}
@@ -0,0 +1,26 @@
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::endpoints::transaction;
+use crate::error::{Error, ErrorCode};
+use crate::types::TransactionPayload;
+use crate::SharedState;
+
+/// Runs all actions of the payload as a single transaction, unless one of the
+/// watched keys no longer has the given version.
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<TransactionPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	match transaction::exec(state, &payload.watch, payload.actions).await {
+		Ok(responses) => Json(responses).into_response(),
+		Err(error) => Json(error).into_response(),
+	}
+}
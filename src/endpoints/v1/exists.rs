@@ -3,26 +3,26 @@ use axum::http::Response;
 use axum::{extract::Path, extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
-use std::sync::{Arc, MutexGuard};
+use std::sync::{Arc, RwLockReadGuard};
 
-use crate::caches::cache::Cache;
+use crate::caches::cache::Shard;
 use crate::error::{Error, ErrorCode};
 use crate::types::KeyPayload;
 use crate::SharedState;
 
-pub fn handle_ws(state: Arc<SharedState>, key: String) -> serde_json::Value {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
+pub fn handle_ws(state: Arc<SharedState>, key: String) -> Result<serde_json::Value, Error> {
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
 
-	match shared_cache.get(&key) {
-		Some(_) => serde_json::to_value(true).unwrap(),
-		None => serde_json::to_value(false).unwrap(),
+	match shard.get(&key) {
+		Some(_) => Ok(serde_json::to_value(true).unwrap()),
+		None => Ok(serde_json::to_value(false).unwrap()),
 	}
 }
 
 pub fn handle(state: Arc<SharedState>, key: String) -> Response<Body> {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
 
-	match shared_cache.get(&key) {
+	match shard.get(&key) {
 		Some(_) => Json(true).into_response(),
 		None => Json(false).into_response(),
 	}
@@ -0,0 +1,57 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::caches::cache::ShardWriteGuard;
+use crate::error::{Error, ErrorCode};
+use crate::types::ExpirePayload;
+use crate::utils::current_time;
+use crate::SharedState;
+
+/// `ttl` is in seconds.
+fn expiration(ttl: u64) -> u128 {
+	current_time() + 1000 * ttl as u128
+}
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	ttl: u64,
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	Ok(serde_json::to_value(shard.set_expiration(&key, expiration(ttl))).unwrap())
+}
+
+pub fn handle(state: Arc<SharedState>, key: String, ttl: u64) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	Json(shard.set_expiration(&key, expiration(ttl))).into_response()
+}
+
+pub async fn handle_get(
+	Path((key, ttl)): Path<(String, u64)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key, ttl)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<ExpirePayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key, payload.ttl)
+}
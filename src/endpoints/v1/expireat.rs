@@ -0,0 +1,56 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::caches::cache::ShardWriteGuard;
+use crate::error::{Error, ErrorCode};
+use crate::types::ExpireAtPayload;
+use crate::SharedState;
+
+/// `timestamp` is a Unix time in seconds, 0 must not turn into `NO_EXPIRATION`.
+fn expiration(timestamp: u64) -> u128 {
+	(1000 * timestamp as u128).max(1)
+}
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	timestamp: u64,
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	Ok(serde_json::to_value(shard.set_expiration(&key, expiration(timestamp))).unwrap())
+}
+
+pub fn handle(state: Arc<SharedState>, key: String, timestamp: u64) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	Json(shard.set_expiration(&key, expiration(timestamp))).into_response()
+}
+
+pub async fn handle_get(
+	Path((key, timestamp)): Path<(String, u64)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key, timestamp)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<ExpireAtPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key, payload.timestamp)
+}
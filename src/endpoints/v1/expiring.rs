@@ -0,0 +1,65 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::caches::cache::Cache;
+use crate::error::{Error, ErrorCode};
+use crate::types::ExpiringPayload;
+use crate::SharedState;
+
+#[derive(Debug, Serialize)]
+pub struct ExpiringKey {
+	pub key: String,
+	pub expiration: u128,
+}
+
+fn expiring(shared_cache: &Cache, seconds: u64, limit: usize) -> Vec<ExpiringKey> {
+	shared_cache
+		.expiring(1000 * seconds as u128, limit)
+		.into_iter()
+		.map(|(key, ttl)| ExpiringKey {
+			key,
+			expiration: ttl / 1000,
+		})
+		.collect()
+}
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	seconds: u64,
+	limit: usize,
+) -> Result<serde_json::Value, Error> {
+	Ok(serde_json::to_value(expiring(&state.cache, seconds, limit)).unwrap())
+}
+
+pub fn handle(state: Arc<SharedState>, seconds: u64, limit: usize) -> Response<Body> {
+	Json(expiring(&state.cache, seconds, limit)).into_response()
+}
+
+pub async fn handle_get(
+	Path((seconds, limit)): Path<(u64, usize)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, seconds, limit)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<ExpiringPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.seconds, payload.limit)
+}
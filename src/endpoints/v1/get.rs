@@ -3,37 +3,63 @@ use axum::{
 };
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::{Arc, MutexGuard};
+use std::sync::{Arc, RwLockReadGuard};
 
-use crate::caches::cache::{Cache, CacheItem};
+use super::cas::etag;
+use crate::caches::cache::{CacheItem, Shard};
+use crate::caches::data::Data;
 use crate::error::{Error, ErrorCode};
 use crate::types::KeyPayload;
 use crate::utils::current_time;
 use crate::SharedState;
 
-pub fn handle_ws(state: Arc<SharedState>, key: String) -> serde_json::Value {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
+/// Value of a key with its remaining time to live in seconds, -1 when it never expires,
+/// and its version for CAS.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetResponse {
+	pub expiration: i64,
+	pub value: Value,
+	pub version: u64,
+}
+
+impl GetResponse {
+	pub fn new(item: &CacheItem, value: &Value) -> Self {
+		GetResponse {
+			expiration: item
+				.ttl(current_time())
+				.map_or(-1, |ttl| (ttl / 1000) as i64),
+			value: value.clone(),
+			version: item.version,
+		}
+	}
+}
+
+pub fn handle_ws(state: Arc<SharedState>, key: String) -> Result<serde_json::Value, Error> {
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
 
-	match shared_cache.get(&key) {
-		Some(item) => serde_json::to_value(CacheItem {
-			value: item.value.clone(),
-			expiration: (item.expiration - current_time()) / 1000,
-		})
-		.unwrap(),
-		None => serde_json::to_value(Value::Null).unwrap(),
+	match shard.get(&key) {
+		Some(item) => match &item.value {
+			Data::Value(value) => Ok(serde_json::to_value(GetResponse::new(item, value)).unwrap()),
+			_ => Err(Error::from_code(ErrorCode::WrongType)),
+		},
+		None => Ok(Value::Null),
 	}
 }
 
 pub fn handle(state: Arc<SharedState>, key: String) -> Response<Body> {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-
-	match shared_cache.get(&key) {
-		Some(item) => Json(CacheItem {
-			value: item.value.clone(),
-			expiration: (item.expiration - current_time()) / 1000,
-		})
-		.into_response(),
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
+
+	match shard.get(&key) {
+		Some(item) => match &item.value {
+			Data::Value(value) => (
+				TypedHeader(etag(item.version)),
+				Json(GetResponse::new(item, value)),
+			)
+				.into_response(),
+			_ => Json(Error::from_code(ErrorCode::WrongType)).into_response(),
+		},
 		None => Json(Value::Null).into_response(),
 	}
 }
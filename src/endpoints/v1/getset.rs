@@ -0,0 +1,80 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::caches::cache::ShardWriteGuard;
+use crate::caches::data::Data;
+use crate::error::{Error, ErrorCode};
+use crate::types::DataPayload;
+use crate::SharedState;
+
+/// Stores `value` and returns the previous value, null when there was none.
+fn get_and_set(
+	shard: &mut ShardWriteGuard,
+	key: String,
+	value: serde_json::Value,
+	ttl: u64,
+) -> Result<serde_json::Value, ErrorCode> {
+	let previous: serde_json::Value = match shard.get(&key).map(|item| &item.value) {
+		Some(Data::Value(previous)) => previous.clone(),
+		Some(_) => return Err(ErrorCode::WrongType),
+		None => serde_json::Value::Null,
+	};
+	shard.set(key, value, 1000 * ttl as u128)?;
+	Ok(previous)
+}
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	value: serde_json::Value,
+	ttl: u64,
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match get_and_set(&mut shard, key, value, ttl) {
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+pub fn handle(
+	state: Arc<SharedState>,
+	key: String,
+	value: serde_json::Value,
+	ttl: u64,
+) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match get_and_set(&mut shard, key, value, ttl) {
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
+}
+
+pub async fn handle_get(
+	Path((key, value, ttl)): Path<(String, serde_json::Value, u64)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key, value, ttl)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<DataPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key, payload.value, payload.ttl)
+}
@@ -0,0 +1,57 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::caches::cache::ShardWriteGuard;
+use crate::error::{Error, ErrorCode};
+use crate::types::FieldsPayload;
+use crate::SharedState;
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	fields: Vec<String>,
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match shard.hdel(&key, fields) {
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+pub fn handle(state: Arc<SharedState>, key: String, fields: Vec<String>) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match shard.hdel(&key, fields) {
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
+}
+
+pub async fn handle_get(
+	Path((key, field)): Path<(String, String)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key, vec![field])
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<FieldsPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key, payload.fields)
+}
@@ -0,0 +1,64 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::{Arc, RwLockReadGuard};
+
+use crate::caches::cache::Shard;
+use crate::caches::hash::Hash;
+use crate::error::{Error, ErrorCode};
+use crate::types::FieldPayload;
+use crate::SharedState;
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	field: String,
+) -> Result<serde_json::Value, Error> {
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
+
+	match shard
+		.collection::<Hash>(&key)
+		.map(|hash| hash.is_some_and(|hash| hash.contains(&field)))
+	{
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+pub fn handle(state: Arc<SharedState>, key: String, field: String) -> Response<Body> {
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
+
+	match shard
+		.collection::<Hash>(&key)
+		.map(|hash| hash.is_some_and(|hash| hash.contains(&field)))
+	{
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
+}
+
+pub async fn handle_get(
+	Path((key, field)): Path<(String, String)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key, field)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<FieldPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key, payload.field)
+}
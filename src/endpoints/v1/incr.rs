@@ -4,92 +4,80 @@ use axum::{extract::Path, extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
 use serde_json::Value;
-use std::sync::{Arc, MutexGuard};
+use std::sync::Arc;
 
-use crate::caches::cache::Cache;
+use crate::caches::cache::ShardWriteGuard;
+use crate::caches::data::Data;
+use crate::caches::events::Event;
 use crate::error::{Error, ErrorCode};
-use crate::types::NumberDataPayload;
+use crate::types::{CounterOptions, NumberDataPayload};
 use crate::utils::current_time;
 use crate::SharedState;
 
-pub fn handle_ws(state: Arc<SharedState>, key: String, value: i64, ttl: u64) -> serde_json::Value {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-
-	let new_value: i64 = match shared_cache.get(&key) {
-		Some(item) => {
-			if let Value::Number(n) = &item.value {
-				if let Some(i) = n.as_i64() {
-					match i.checked_add(value) {
-						Some(result) => result,
-						None => {
-							return serde_json::to_value(Error::from_code(ErrorCode::IntegerOverflow)).unwrap();
-						}
-					}
-				} else {
-					return serde_json::to_value(Error::from_code(ErrorCode::InvalidInteger)).unwrap();
-				}
-			} else {
-				return serde_json::to_value(Error::from_code(ErrorCode::InvalidNumber)).unwrap();
-			}
-		}
-		None => 1,
-	};
+/// Error for a key that does not hold a number.
+pub fn not_a_number(data: &Data) -> ErrorCode {
+	match data {
+		Data::Value(_) => ErrorCode::InvalidNumber,
+		_ => ErrorCode::WrongType,
+	}
+}
 
-	let new_ttl: u128 = match shared_cache.get(&key) {
-		Some(item) => {
-			let current_time: u128 = current_time();
-			if item.expiration > current_time {
-				item.expiration - current_time
-			} else {
-				0
-			}
-		}
-		None => 1000 * ttl as u128,
+/// Adds `delta` to the integer stored under `key`. A missing key starts at the
+/// initial value of `options` and expires after `ttl` seconds. Returns the new value.
+pub fn increment(
+	shard: &mut ShardWriteGuard,
+	key: String,
+	delta: i64,
+	ttl: u64,
+	options: CounterOptions,
+) -> Result<i64, ErrorCode> {
+	let (current, new_ttl): (i64, u128) = match shard.get(&key) {
+		Some(item) => match &item.value {
+			Data::Value(Value::Number(n)) => (
+				n.as_i64().ok_or(ErrorCode::InvalidInteger)?,
+				item.ttl(current_time()).map_or(0, |ttl| ttl.max(1)),
+			),
+			data => return Err(not_a_number(data)),
+		},
+		None => (options.initial.unwrap_or(0), 1000 * ttl as u128),
 	};
 
-	shared_cache.set(key.clone(), Value::Number(new_value.into()), new_ttl);
-
-	serde_json::to_value(Error::from_code(ErrorCode::Success)).unwrap()
+	let new_value: i64 = current
+		.checked_add(delta)
+		.ok_or(ErrorCode::IntegerOverflow)?;
+	let new_value: i64 = options.bound(new_value)?;
+	shard.set_as(key, Value::Number(new_value.into()), new_ttl, Event::Incr)?;
+	Ok(new_value)
 }
 
-pub fn handle(state: Arc<SharedState>, key: String, value: i64, ttl: u64) -> Response<Body> {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	value: i64,
+	ttl: u64,
+	options: CounterOptions,
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
 
-	let new_value: i64 = match shared_cache.get(&key) {
-		Some(item) => {
-			if let Value::Number(n) = &item.value {
-				if let Some(i) = n.as_i64() {
-					match i.checked_add(value) {
-						Some(result) => result,
-						None => {
-							return Json(Error::from_code(ErrorCode::IntegerOverflow)).into_response();
-						}
-					}
-				} else {
-					return Json(Error::from_code(ErrorCode::InvalidInteger)).into_response();
-				}
-			} else {
-				return Json(Error::from_code(ErrorCode::InvalidNumber)).into_response();
-			}
-		}
-		None => 1,
-	};
-
-	let new_ttl: u128 = match shared_cache.get(&key) {
-		Some(item) => {
-			let current_time: u128 = current_time();
-			if item.expiration > current_time {
-				item.expiration - current_time
-			} else {
-				0
-			}
-		}
-		None => 1000 * ttl as u128,
-	};
+	match increment(&mut shard, key, value, ttl, options) {
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
 
-	shared_cache.set(key.clone(), Value::Number(new_value.into()), new_ttl);
+pub fn handle(
+	state: Arc<SharedState>,
+	key: String,
+	value: i64,
+	ttl: u64,
+	options: CounterOptions,
+) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
 
-	Json(Error::from_code(ErrorCode::Success)).into_response()
+	match increment(&mut shard, key, value, ttl, options) {
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
 }
 
 pub async fn handle_get(
@@ -101,7 +89,7 @@ pub async fn handle_get(
 		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
 	}
 
-	handle(state, key, value, ttl)
+	handle(state, key, value, ttl, CounterOptions::default())
 }
 
 pub async fn handle_post(
@@ -113,5 +101,11 @@ pub async fn handle_post(
 		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
 	}
 
-	handle(state, payload.key, payload.value, payload.ttl)
+	handle(
+		state,
+		payload.key,
+		payload.value,
+		payload.ttl,
+		payload.options,
+	)
 }
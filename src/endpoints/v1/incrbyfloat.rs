@@ -0,0 +1,88 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use serde_json::{Number, Value};
+use std::sync::Arc;
+
+use super::incr::not_a_number;
+use crate::caches::cache::ShardWriteGuard;
+use crate::caches::data::Data;
+use crate::caches::events::Event;
+use crate::error::{Error, ErrorCode};
+use crate::types::FloatDataPayload;
+use crate::utils::current_time;
+use crate::SharedState;
+
+/// Adds `delta` to the number stored under `key`, a missing key starts at 0
+/// and expires after `ttl` seconds. Returns the new value.
+pub fn increment_float(
+	shard: &mut ShardWriteGuard,
+	key: String,
+	delta: f64,
+	ttl: u64,
+) -> Result<f64, ErrorCode> {
+	let (current, new_ttl): (f64, u128) = match shard.get(&key) {
+		Some(item) => match &item.value {
+			Data::Value(Value::Number(n)) => (
+				n.as_f64().ok_or(ErrorCode::InvalidNumber)?,
+				item.ttl(current_time()).map_or(0, |ttl| ttl.max(1)),
+			),
+			data => return Err(not_a_number(data)),
+		},
+		None => (0.0, 1000 * ttl as u128),
+	};
+
+	let new_value: f64 = current + delta;
+	let number: Number = Number::from_f64(new_value).ok_or(ErrorCode::InvalidFloat)?;
+	shard.set_as(key, Value::Number(number), new_ttl, Event::Incr)?;
+	Ok(new_value)
+}
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	value: f64,
+	ttl: u64,
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match increment_float(&mut shard, key, value, ttl) {
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+pub fn handle(state: Arc<SharedState>, key: String, value: f64, ttl: u64) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	match increment_float(&mut shard, key, value, ttl) {
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
+}
+
+pub async fn handle_get(
+	Path((key, value, ttl)): Path<(String, f64, u64)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key, value, ttl)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<FloatDataPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key, payload.value, payload.ttl)
+}
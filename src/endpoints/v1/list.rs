@@ -3,9 +3,8 @@ use axum::http::Response;
 use axum::{extract::Path, extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
-use std::sync::{Arc, MutexGuard};
+use std::sync::Arc;
 
-use crate::caches::cache::Cache;
 use crate::error::{Error, ErrorCode};
 use crate::types::ListPayload;
 use crate::SharedState;
@@ -15,10 +14,8 @@ pub fn handle_ws(
 	prefix: String,
 	limit: usize,
 	cursor: usize,
-) -> serde_json::Value {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-
-	serde_json::to_value(shared_cache.list(limit, cursor, &prefix)).unwrap()
+) -> Result<serde_json::Value, Error> {
+	Ok(serde_json::to_value(state.cache.list(limit, cursor, &prefix)).unwrap())
 }
 
 pub fn handle(
@@ -27,9 +24,7 @@ pub fn handle(
 	limit: usize,
 	cursor: usize,
 ) -> Response<Body> {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-
-	Json(shared_cache.list(limit, cursor, &prefix)).into_response()
+	Json(state.cache.list(limit, cursor, &prefix)).into_response()
 }
 
 pub async fn handle_get(
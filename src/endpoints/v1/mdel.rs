@@ -0,0 +1,47 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::caches::cache::{ShardWriteGuard, ShardsWriteGuard};
+use crate::error::{Error, ErrorCode};
+use crate::types::KeysPayload;
+use crate::SharedState;
+
+/// Deletes `keys` at once and returns for each of them whether it existed.
+fn delete_many(state: &SharedState, keys: &[String]) -> Vec<bool> {
+	let mut shards: ShardsWriteGuard = state.cache.write_many(keys);
+	keys
+		.iter()
+		.map(|key| {
+			let shard: &mut ShardWriteGuard = shards.shard(key);
+			let existed: bool = shard.get(key).is_some();
+			if existed {
+				shard.delete(key);
+			}
+			existed
+		})
+		.collect()
+}
+
+pub fn handle_ws(state: Arc<SharedState>, keys: Vec<String>) -> Result<serde_json::Value, Error> {
+	Ok(serde_json::to_value(delete_many(&state, &keys)).unwrap())
+}
+
+pub fn handle(state: Arc<SharedState>, keys: Vec<String>) -> Response<Body> {
+	Json(delete_many(&state, &keys)).into_response()
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<KeysPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.keys)
+}
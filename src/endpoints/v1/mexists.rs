@@ -0,0 +1,39 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::caches::cache::ShardsReadGuard;
+use crate::error::{Error, ErrorCode};
+use crate::types::KeysPayload;
+use crate::SharedState;
+
+fn exists_many(state: &SharedState, keys: &[String]) -> Vec<bool> {
+	let shards: ShardsReadGuard = state.cache.read_many(keys);
+	keys
+		.iter()
+		.map(|key| shards.shard(key).get(key).is_some())
+		.collect()
+}
+
+pub fn handle_ws(state: Arc<SharedState>, keys: Vec<String>) -> Result<serde_json::Value, Error> {
+	Ok(serde_json::to_value(exists_many(&state, &keys)).unwrap())
+}
+
+pub fn handle(state: Arc<SharedState>, keys: Vec<String>) -> Response<Body> {
+	Json(exists_many(&state, &keys)).into_response()
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<KeysPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.keys)
+}
@@ -0,0 +1,48 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use super::get::GetResponse;
+use crate::caches::cache::ShardsReadGuard;
+use crate::caches::data::Data;
+use crate::error::{Error, ErrorCode};
+use crate::types::KeysPayload;
+use crate::SharedState;
+
+/// Results for `keys` in order, each as `get` would return it, read at a single point in time.
+fn get_many(state: &SharedState, keys: &[String]) -> Vec<serde_json::Value> {
+	let shards: ShardsReadGuard = state.cache.read_many(keys);
+	keys
+		.iter()
+		.map(|key| match shards.shard(key).get(key) {
+			Some(item) => match &item.value {
+				Data::Value(value) => serde_json::to_value(GetResponse::new(item, value)).unwrap(),
+				_ => serde_json::to_value(Error::from_code(ErrorCode::WrongType)).unwrap(),
+			},
+			None => serde_json::Value::Null,
+		})
+		.collect()
+}
+
+pub fn handle_ws(state: Arc<SharedState>, keys: Vec<String>) -> Result<serde_json::Value, Error> {
+	Ok(serde_json::to_value(get_many(&state, &keys)).unwrap())
+}
+
+pub fn handle(state: Arc<SharedState>, keys: Vec<String>) -> Response<Body> {
+	Json(get_many(&state, &keys)).into_response()
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<KeysPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.keys)
+}
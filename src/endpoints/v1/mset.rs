@@ -0,0 +1,158 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::caches::cache::ShardsWriteGuard;
+use crate::error::{Error, ErrorCode};
+use crate::types::{DataPayload, MultiSetPayload};
+use crate::SharedState;
+
+/// Stores all `items` at once, or none of them when they do not fit into the
+/// memory or key limit. Returns the versions of the new values in order.
+fn set_many(state: &SharedState, items: Vec<DataPayload>) -> Result<Vec<u64>, ErrorCode> {
+	let keys: Vec<String> = items.iter().map(|item| item.key.clone()).collect();
+	let mut shards: ShardsWriteGuard = state.cache.write_many(&keys);
+	shards.set_all(
+		items
+			.into_iter()
+			.map(|item| (item.key, item.value, 1000 * item.ttl as u128))
+			.collect(),
+	)
+}
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	items: Vec<DataPayload>,
+) -> Result<serde_json::Value, Error> {
+	match set_many(&state, items) {
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+pub fn handle(state: Arc<SharedState>, items: Vec<DataPayload>) -> Response<Body> {
+	match set_many(&state, items) {
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<MultiSetPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.items)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::cache::{Cache, ShardsReadGuard};
+	use crate::caches::data::Data;
+	use crate::caches::eviction::{EvictionPolicy, Limits};
+	use crate::caches::snapshot::SnapshotOptions;
+	use serde_json::json;
+
+	fn state(limits: Limits) -> Arc<SharedState> {
+		SharedState::for_test(Cache::new(
+			String::new(),
+			false,
+			SnapshotOptions::default(),
+			limits,
+			4,
+		))
+	}
+
+	fn items(keys: &[&str], value: i64) -> Vec<DataPayload> {
+		keys
+			.iter()
+			.map(|key| DataPayload {
+				key: key.to_string(),
+				value: json!(value),
+				ttl: 0,
+			})
+			.collect()
+	}
+
+	fn values(state: &SharedState, keys: &[&str]) -> Vec<Option<serde_json::Value>> {
+		let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+		let shards: ShardsReadGuard = state.cache.read_many(&keys);
+		keys
+			.iter()
+			.map(|key| match &shards.shard(key).get(key)?.value {
+				Data::Value(value) => Some(value.clone()),
+				_ => None,
+			})
+			.collect()
+	}
+
+	const KEYS: [&str; 3] = ["a", "b", "c"];
+
+	#[test]
+	fn stores_nothing_when_the_items_do_not_fit() {
+		let state: Arc<SharedState> = state(Limits {
+			max_keys: 2,
+			..Limits::default()
+		});
+		assert!(matches!(
+			set_many(&state, items(&KEYS, 1)),
+			Err(ErrorCode::OutOfMemory)
+		));
+		assert_eq!(state.cache.key_count(), 0);
+
+		assert_eq!(set_many(&state, items(&KEYS[..2], 1)).unwrap().len(), 2);
+	}
+
+	#[test]
+	fn counts_repeated_keys_once() {
+		let state: Arc<SharedState> = state(Limits {
+			max_keys: 2,
+			..Limits::default()
+		});
+		assert_eq!(
+			set_many(&state, items(&["a", "b", "a"], 1)).unwrap().len(),
+			3
+		);
+		assert_eq!(state.cache.key_count(), 2);
+	}
+
+	#[test]
+	fn evicts_only_keys_outside_the_items() {
+		let state: Arc<SharedState> = state(Limits {
+			max_keys: 3,
+			policy: EvictionPolicy::AllkeysLru,
+			..Limits::default()
+		});
+		set_many(&state, items(&["old"], 1)).unwrap();
+
+		set_many(&state, items(&KEYS, 2)).unwrap();
+		assert_eq!(values(&state, &["old"]), vec![None]);
+		assert_eq!(values(&state, &KEYS), vec![Some(json!(2)); 3]);
+	}
+
+	#[test]
+	fn readers_see_all_items_or_none() {
+		let state: Arc<SharedState> = state(Limits::default());
+		set_many(&state, items(&KEYS, 0)).unwrap();
+
+		std::thread::scope(|scope| {
+			scope.spawn(|| {
+				for value in 1..=200 {
+					set_many(&state, items(&KEYS, value)).unwrap();
+				}
+			});
+			for _ in 0..200 {
+				let seen: Vec<Option<serde_json::Value>> = values(&state, &KEYS);
+				assert!(seen.iter().all(|value| *value == seen[0]), "{:?}", seen);
+			}
+		});
+	}
+}
@@ -0,0 +1,55 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::caches::cache::{ShardWriteGuard, NO_EXPIRATION};
+use crate::error::{Error, ErrorCode};
+use crate::types::KeyPayload;
+use crate::SharedState;
+
+/// Removes the expiration of `key`, returns `false` when it has none or does not exist.
+fn persist(shard: &mut ShardWriteGuard, key: &str) -> bool {
+	match shard.get(key).map(|item| item.expiration) {
+		Some(NO_EXPIRATION) | None => false,
+		Some(_) => shard.set_expiration(key, NO_EXPIRATION),
+	}
+}
+
+pub fn handle_ws(state: Arc<SharedState>, key: String) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	Ok(serde_json::to_value(persist(&mut shard, &key)).unwrap())
+}
+
+pub fn handle(state: Arc<SharedState>, key: String) -> Response<Body> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+
+	Json(persist(&mut shard, &key)).into_response()
+}
+
+pub async fn handle_get(
+	Path(key): Path<String>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<KeyPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key)
+}
@@ -8,8 +8,8 @@ use std::sync::Arc;
 use crate::error::{Error, ErrorCode};
 use crate::SharedState;
 
-pub fn handle_ws() -> serde_json::Value {
-	serde_json::to_value(Error::from_code(ErrorCode::Success)).unwrap()
+pub fn handle_ws() -> Result<serde_json::Value, Error> {
+	Ok(serde_json::Value::Null)
 }
 
 pub fn handle() -> Response<Body> {
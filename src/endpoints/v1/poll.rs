@@ -0,0 +1,152 @@
+use axum::{
+	body::Body, extract::Path, extract::State, http::Response, response::IntoResponse, Json,
+};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::{Arc, RwLockReadGuard};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{Duration, Instant};
+
+use crate::caches::cache::Shard;
+use crate::caches::events::{Event, KeyEvent};
+use crate::error::{Error, ErrorCode};
+use crate::state::Scope;
+use crate::types::PollPayload;
+use crate::SharedState;
+
+/// Whether `key` changed since the caller saw `version`. A key that is gone
+/// changed unless the caller never saw it.
+fn changed(state: &SharedState, key: &str, version: u64) -> bool {
+	let shard: RwLockReadGuard<Shard> = state.cache.read(key);
+	match shard.get(key) {
+		Some(item) => item.version > version,
+		None => version > 0,
+	}
+}
+
+/// Waits up to `timeout` seconds, or forever when it is 0, until the version of
+/// `key` passes `version`, and then answers like `/v1/get`. On timeout the
+/// unchanged value is returned, so callers can tell by its version.
+pub async fn handle(
+	state: Arc<SharedState>,
+	key: String,
+	version: u64,
+	timeout: u64,
+) -> Response<Body> {
+	let deadline: Option<Instant> =
+		(timeout > 0).then(|| Instant::now() + Duration::from_secs(timeout));
+	// Listen before looking, so a change in between is not missed.
+	let mut events: broadcast::Receiver<KeyEvent> = state.cache.events();
+
+	while !changed(&state, &key, version) {
+		tokio::select! {
+			received = events.recv() => match received {
+				Ok(event) if event.key != key && event.event != Event::Flush => continue,
+				Ok(_) | Err(RecvError::Lagged(_)) => {}
+				Err(RecvError::Closed) => break,
+			},
+			_ = async {
+				match deadline {
+					Some(deadline) => tokio::time::sleep_until(deadline).await,
+					None => std::future::pending().await,
+				}
+			} => break,
+			_ = state.shutdown_requested() => break,
+		}
+	}
+
+	let _operation = state.operation(&Scope::Keys(vec![key.clone()])).await;
+	super::get::handle(state.clone(), key)
+}
+
+pub async fn handle_get(
+	Path((key, version, timeout)): Path<(String, u64, u64)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key, version, timeout).await
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<PollPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key, payload.version, payload.timeout).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::cache::Cache;
+	use crate::caches::eviction::Limits;
+	use crate::caches::snapshot::SnapshotOptions;
+	use serde_json::json;
+
+	fn state() -> Arc<SharedState> {
+		SharedState::for_test(Cache::new(
+			String::new(),
+			false,
+			SnapshotOptions::default(),
+			Limits::default(),
+			4,
+		))
+	}
+
+	fn set(state: &SharedState, key: &str, value: i64) -> u64 {
+		state
+			.cache
+			.write(key)
+			.set(key.to_string(), json!(value), 0)
+			.unwrap()
+	}
+
+	async fn body(response: Response<Body>) -> serde_json::Value {
+		let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+			.await
+			.unwrap();
+		serde_json::from_slice(&bytes).unwrap()
+	}
+
+	#[tokio::test]
+	async fn answers_right_away_when_the_key_is_newer() {
+		let state: Arc<SharedState> = state();
+		let version: u64 = set(&state, "a", 1);
+
+		let response = body(handle(state, "a".to_string(), version - 1, 0).await).await;
+		assert_eq!(response["value"], json!(1));
+		assert_eq!(response["version"], json!(version));
+	}
+
+	#[tokio::test]
+	async fn waits_for_a_change_of_the_key() {
+		let state: Arc<SharedState> = state();
+		let version: u64 = set(&state, "a", 1);
+		let waiting = tokio::spawn(handle(state.clone(), "a".to_string(), version, 0));
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		set(&state, "b", 1);
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		assert!(!waiting.is_finished());
+
+		set(&state, "a", 2);
+		let response = body(waiting.await.unwrap()).await;
+		assert_eq!(response["value"], json!(2));
+	}
+
+	#[tokio::test]
+	async fn answers_with_the_unchanged_value_on_timeout() {
+		let state: Arc<SharedState> = state();
+		let version: u64 = set(&state, "a", 1);
+
+		let response = body(handle(state, "a".to_string(), version, 1).await).await;
+		assert_eq!(response["version"], json!(version));
+	}
+}
@@ -0,0 +1,38 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::error::{Error, ErrorCode};
+use crate::types::PublishPayload;
+use crate::SharedState;
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	channel: String,
+	message: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+	Ok(serde_json::to_value(state.pubsub.publish(&channel, message)).unwrap())
+}
+
+pub fn handle(
+	state: Arc<SharedState>,
+	channel: String,
+	message: serde_json::Value,
+) -> Response<Body> {
+	Json(state.pubsub.publish(&channel, message)).into_response()
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<PublishPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.channel, payload.message)
+}
@@ -3,25 +3,20 @@ use axum::http::Response;
 use axum::{extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
-use std::sync::{Arc, MutexGuard};
+use std::sync::Arc;
 
-use crate::caches::cache::Cache;
 use crate::error::{Error, ErrorCode};
 use crate::SharedState;
 
-pub fn handle_ws(state: Arc<SharedState>) -> serde_json::Value {
-	let shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-
-	match shared_cache.save() {
-		Ok(_) => serde_json::to_value(Error::from_code(ErrorCode::Success)).unwrap(),
-		Err(_) => serde_json::to_value(Error::from_code(ErrorCode::WriteToFile)).unwrap(),
+pub fn handle_ws(state: Arc<SharedState>) -> Result<serde_json::Value, Error> {
+	match state.cache.save() {
+		Ok(_) => Ok(serde_json::Value::Null),
+		Err(_) => Err(Error::from_code(ErrorCode::WriteToFile)),
 	}
 }
 
 pub fn handle(state: Arc<SharedState>) -> Response<Body> {
-	let shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-
-	match shared_cache.save() {
+	match state.cache.save() {
 		Ok(_) => Json(Error::from_code(ErrorCode::Success)).into_response(),
 		Err(_) => Json(Error::from_code(ErrorCode::WriteToFile)).into_response(),
 	}
@@ -0,0 +1,35 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::error::{Error, ErrorCode};
+use crate::types::ScriptPayload;
+use crate::SharedState;
+
+/// Compiles `script` and returns the hash to run it by with EVALSHA.
+pub fn handle_ws(state: Arc<SharedState>, script: String) -> Result<serde_json::Value, Error> {
+	let (hash, _) = state.scripts.load(&script)?;
+	Ok(serde_json::to_value(hash).unwrap())
+}
+
+pub fn handle(state: Arc<SharedState>, script: String) -> Response<Body> {
+	match state.scripts.load(&script) {
+		Ok((hash, _)) => Json(hash).into_response(),
+		Err(error) => Json(error).into_response(),
+	}
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<ScriptPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.script)
+}
@@ -1,11 +1,12 @@
 use axum::body::Body;
-use axum::http::Response;
+use axum::http::{HeaderMap, Response, StatusCode};
 use axum::{extract::Path, extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
-use headers::{authorization::Bearer, Authorization};
-use std::sync::{Arc, MutexGuard};
+use headers::{authorization::Bearer, Authorization, HeaderMapExt, IfMatch};
+use std::sync::Arc;
 
-use crate::caches::cache::Cache;
+use super::cas::etag;
+use crate::caches::cache::ShardWriteGuard;
 use crate::error::{Error, ErrorCode};
 use crate::types::DataPayload;
 use crate::SharedState;
@@ -15,45 +16,76 @@ pub fn handle_ws(
 	key: String,
 	value: serde_json::Value,
 	ttl: u64,
-) -> serde_json::Value {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-	shared_cache.set(key, value, 1000 * ttl as u128);
+) -> Result<serde_json::Value, Error> {
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+	if let Err(code) = shard.set(key, value, 1000 * ttl as u128) {
+		return Err(Error::from_code(code));
+	}
 
-	serde_json::to_value(Error::from_code(ErrorCode::Success)).unwrap()
+	Ok(serde_json::Value::Null)
 }
 
+/// Stores `value` unless `if_match` is given and does not match the current version,
+/// in which case 412 Precondition Failed is returned.
 pub fn handle(
 	state: Arc<SharedState>,
 	key: String,
 	value: serde_json::Value,
 	ttl: u64,
+	if_match: Option<IfMatch>,
 ) -> Response<Body> {
-	let mut shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-	shared_cache.set(key, value, 1000 * ttl as u128);
+	let mut shard: ShardWriteGuard = state.cache.write(&key);
+	if let Some(if_match) = if_match {
+		let matches: bool = shard
+			.get(&key)
+			.is_some_and(|item| if_match.precondition_passes(&etag(item.version)));
+		if !matches {
+			return (
+				StatusCode::PRECONDITION_FAILED,
+				Json(Error::from_code(ErrorCode::Conflict)),
+			)
+				.into_response();
+		}
+	}
 
-	Json(Error::from_code(ErrorCode::Success)).into_response()
+	match shard.set(key, value, 1000 * ttl as u128) {
+		Ok(version) => (
+			TypedHeader(etag(version)),
+			Json(Error::from_code(ErrorCode::Success)),
+		)
+			.into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
 }
 
 pub async fn handle_get(
 	Path((key, value, ttl)): Path<(String, serde_json::Value, u64)>,
 	State(state): State<Arc<SharedState>>,
 	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	headers: HeaderMap,
 ) -> impl IntoResponse {
 	if state.token.ne(bearer_token.token()) {
 		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
 	}
 
-	Json(handle_ws(state, key, value, ttl)).into_response()
+	match headers.typed_try_get::<IfMatch>() {
+		Ok(if_match) => handle(state, key, value, ttl, if_match),
+		Err(_) => Json(Error::from_code(ErrorCode::InvalidData)).into_response(),
+	}
 }
 
 pub async fn handle_post(
 	State(state): State<Arc<SharedState>>,
 	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	headers: HeaderMap,
 	Json(payload): Json<DataPayload>,
 ) -> impl IntoResponse {
 	if state.token.ne(bearer_token.token()) {
 		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
 	}
 
-	handle(state, payload.key, payload.value, payload.ttl)
+	match headers.typed_try_get::<IfMatch>() {
+		Ok(if_match) => handle(state, payload.key, payload.value, payload.ttl, if_match),
+		Err(_) => Json(Error::from_code(ErrorCode::InvalidData)).into_response(),
+	}
 }
@@ -0,0 +1,37 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::Arc;
+
+use crate::caches::set::SetOperation;
+use crate::error::{Error, ErrorCode};
+use crate::types::KeysPayload;
+use crate::SharedState;
+
+pub fn handle_ws(state: Arc<SharedState>, keys: Vec<String>) -> Result<serde_json::Value, Error> {
+	match state.cache.combine_sets(&keys, SetOperation::Inter) {
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+pub fn handle(state: Arc<SharedState>, keys: Vec<String>) -> Response<Body> {
+	match state.cache.combine_sets(&keys, SetOperation::Inter) {
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<KeysPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.keys)
+}
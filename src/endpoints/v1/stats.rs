@@ -3,22 +3,17 @@ use axum::http::Response;
 use axum::{extract::State, response::IntoResponse, Json};
 use axum_extra::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
-use std::sync::{Arc, MutexGuard};
+use std::sync::Arc;
 
-use crate::caches::cache::Cache;
 use crate::error::{Error, ErrorCode};
 use crate::SharedState;
 
-pub fn handle_ws(state: Arc<SharedState>) -> serde_json::Value {
-	let shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-
-	serde_json::to_value(&shared_cache.stats).unwrap()
+pub fn handle_ws(state: Arc<SharedState>) -> Result<serde_json::Value, Error> {
+	Ok(serde_json::to_value(state.cache.stats()).unwrap())
 }
 
 pub fn handle(state: Arc<SharedState>) -> Response<Body> {
-	let shared_cache: MutexGuard<Cache> = state.cache.lock().unwrap();
-
-	Json(&shared_cache.stats).into_response()
+	Json(state.cache.stats()).into_response()
 }
 
 pub async fn handle_get(
@@ -0,0 +1,59 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::{Arc, RwLockReadGuard};
+
+use crate::caches::cache::Shard;
+use crate::error::{Error, ErrorCode};
+use crate::types::KeyPayload;
+use crate::utils::current_time;
+use crate::SharedState;
+
+/// Remaining time to live of `key` in units of `unit` milliseconds,
+/// -2 when the key does not exist and -1 when it never expires.
+pub fn remaining(shard: &Shard, key: &str, unit: u128) -> i64 {
+	match shard.get(key) {
+		Some(item) => item
+			.ttl(current_time())
+			.map_or(-1, |ttl| (ttl / unit) as i64),
+		None => -2,
+	}
+}
+
+pub fn handle_ws(state: Arc<SharedState>, key: String) -> Result<serde_json::Value, Error> {
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
+
+	Ok(serde_json::to_value(remaining(&shard, &key, 1000)).unwrap())
+}
+
+pub fn handle(state: Arc<SharedState>, key: String) -> Response<Body> {
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
+
+	Json(remaining(&shard, &key, 1000)).into_response()
+}
+
+pub async fn handle_get(
+	Path(key): Path<String>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<KeyPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload.key)
+}
@@ -0,0 +1,204 @@
+use axum::extract::{Path, State};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::{body::Body, http::Response, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use futures::stream::{self, Stream};
+use headers::{authorization::Bearer, Authorization};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::caches::events::{Event, KeyEvent};
+use crate::error::{Error, ErrorCode};
+use crate::types::WatchPayload;
+use crate::SharedState;
+
+impl WatchPayload {
+	/// Whether `event` concerns the watched keys, a flush concerns all of them.
+	fn matches(&self, event: &KeyEvent) -> bool {
+		let key: &str = &event.key;
+		event.event == Event::Flush
+			|| self.key.as_ref().is_none_or(|watched| watched == key)
+				&& self
+					.prefix
+					.as_ref()
+					.is_none_or(|prefix| key.starts_with(prefix.as_str()))
+	}
+}
+
+/// Changes to the watched keys as they happen. Each one is sent as an event named
+/// after what happened with the key event as JSON data. A "lagged" event with the
+/// number of missed changes is sent when the client reads too slowly to keep up.
+fn events(
+	state: Arc<SharedState>,
+	payload: WatchPayload,
+) -> impl Stream<Item = Result<SseEvent, Infallible>> {
+	let receiver: broadcast::Receiver<KeyEvent> = state.cache.events();
+	stream::unfold(
+		(state, receiver, payload),
+		|(state, mut receiver, payload)| async move {
+			let event: SseEvent = loop {
+				tokio::select! {
+					received = receiver.recv() => match received {
+						Ok(event) if payload.matches(&event) => {
+							break SseEvent::default()
+								.event(event.event.name())
+								.json_data(&event)
+								.unwrap();
+						}
+						Ok(_) => {}
+						Err(RecvError::Lagged(missed)) => {
+							break SseEvent::default().event("lagged").data(missed.to_string());
+						}
+						Err(RecvError::Closed) => return None,
+					},
+					_ = state.shutdown_requested() => return None,
+				}
+			};
+			Some((Ok(event), (state, receiver, payload)))
+		},
+	)
+}
+
+pub fn handle(state: Arc<SharedState>, payload: WatchPayload) -> Response<Body> {
+	Sse::new(events(state, payload))
+		.keep_alive(KeepAlive::default())
+		.into_response()
+}
+
+pub async fn handle_get(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, WatchPayload::default())
+}
+
+pub async fn handle_get_key(
+	Path(key): Path<String>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	let payload: WatchPayload = WatchPayload {
+		key: Some(key),
+		prefix: None,
+	};
+	handle(state, payload)
+}
+
+pub async fn handle_get_prefix(
+	Path(prefix): Path<String>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	let payload: WatchPayload = WatchPayload {
+		key: None,
+		prefix: Some(prefix),
+	};
+	handle(state, payload)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<WatchPayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, payload)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::cache::Cache;
+	use crate::caches::eviction::Limits;
+	use crate::caches::snapshot::SnapshotOptions;
+	use futures::StreamExt;
+	use std::time::Duration;
+
+	fn state() -> Arc<SharedState> {
+		SharedState::for_test(Cache::new(
+			String::new(),
+			false,
+			SnapshotOptions::default(),
+			Limits::default(),
+			4,
+		))
+	}
+
+	fn set(state: &SharedState, key: &str) {
+		state
+			.cache
+			.write(key)
+			.set(key.to_string(), 1.into(), 0)
+			.unwrap();
+	}
+
+	#[tokio::test]
+	async fn sends_changes_to_matching_keys() {
+		let state: Arc<SharedState> = state();
+		let payload: WatchPayload = WatchPayload {
+			key: None,
+			prefix: Some("user:".to_string()),
+		};
+		let mut events = handle(state.clone(), payload)
+			.into_body()
+			.into_data_stream();
+
+		set(&state, "other");
+		set(&state, "user:1");
+		let event = tokio::time::timeout(Duration::from_secs(1), events.next())
+			.await
+			.unwrap()
+			.unwrap()
+			.unwrap();
+		let event: &str = std::str::from_utf8(&event).unwrap();
+		assert!(event.starts_with("event: set\n"), "{}", event);
+		assert!(event.contains(r#""key":"user:1""#), "{}", event);
+	}
+
+	#[test]
+	fn matches_by_key_and_prefix() {
+		let watch = |key: Option<&str>, prefix: Option<&str>| WatchPayload {
+			key: key.map(String::from),
+			prefix: prefix.map(String::from),
+		};
+		let set = |key: &str| KeyEvent {
+			event: Event::Set,
+			key: key.to_string(),
+		};
+		assert!(watch(None, None).matches(&set("anything")));
+		assert!(watch(Some("a"), None).matches(&set("a")));
+		assert!(!watch(Some("a"), None).matches(&set("ab")));
+		assert!(watch(None, Some("a")).matches(&set("ab")));
+		assert!(!watch(None, Some("a")).matches(&set("ba")));
+		assert!(!watch(Some("ab"), Some("b")).matches(&set("ab")));
+	}
+
+	#[test]
+	fn matches_a_flush_for_any_key() {
+		let flush: KeyEvent = KeyEvent {
+			event: Event::Flush,
+			key: String::new(),
+		};
+		let watch: WatchPayload = WatchPayload {
+			key: Some("a".to_string()),
+			prefix: None,
+		};
+		assert!(watch.matches(&flush));
+	}
+}
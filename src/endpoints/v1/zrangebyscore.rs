@@ -0,0 +1,83 @@
+use axum::body::Body;
+use axum::http::Response;
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use axum_extra::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use std::sync::{Arc, RwLockReadGuard};
+
+use crate::caches::cache::Shard;
+use crate::caches::zset::SortedSet;
+use crate::error::{Error, ErrorCode};
+use crate::types::ScoreRangePayload;
+use crate::SharedState;
+
+pub fn handle_ws(
+	state: Arc<SharedState>,
+	key: String,
+	min: f64,
+	max: f64,
+	offset: Option<usize>,
+	count: Option<usize>,
+) -> Result<serde_json::Value, Error> {
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
+
+	match shard.collection::<SortedSet>(&key).map(|set| {
+		set.map_or_else(Vec::new, |set| {
+			set.range_by_score(min, max, offset.unwrap_or(0), count.unwrap_or(usize::MAX))
+		})
+	}) {
+		Ok(result) => Ok(serde_json::to_value(result).unwrap()),
+		Err(code) => Err(Error::from_code(code)),
+	}
+}
+
+pub fn handle(
+	state: Arc<SharedState>,
+	key: String,
+	min: f64,
+	max: f64,
+	offset: Option<usize>,
+	count: Option<usize>,
+) -> Response<Body> {
+	let shard: RwLockReadGuard<Shard> = state.cache.read(&key);
+
+	match shard.collection::<SortedSet>(&key).map(|set| {
+		set.map_or_else(Vec::new, |set| {
+			set.range_by_score(min, max, offset.unwrap_or(0), count.unwrap_or(usize::MAX))
+		})
+	}) {
+		Ok(result) => Json(result).into_response(),
+		Err(code) => Json(Error::from_code(code)).into_response(),
+	}
+}
+
+pub async fn handle_get(
+	Path((key, min, max)): Path<(String, f64, f64)>,
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(state, key, min, max, None, None)
+}
+
+pub async fn handle_post(
+	State(state): State<Arc<SharedState>>,
+	TypedHeader(bearer_token): TypedHeader<Authorization<Bearer>>,
+	Json(payload): Json<ScoreRangePayload>,
+) -> impl IntoResponse {
+	if state.token.ne(bearer_token.token()) {
+		return Json(Error::from_code(ErrorCode::InvalidToken)).into_response();
+	}
+
+	handle(
+		state,
+		payload.key,
+		payload.min,
+		payload.max,
+		payload.offset,
+		payload.count,
+	)
+}
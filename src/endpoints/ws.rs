@@ -1,6 +1,6 @@
 use axum::{
 	extract::{
-		ws::{Message, WebSocket, WebSocketUpgrade},
+		ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
 		Path, State,
 	},
 	response::IntoResponse,
@@ -12,12 +12,10 @@ use std::ops::ControlFlow;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
+use super::transaction::Transaction;
 use crate::error::Error;
-use crate::{
-	error::ErrorCode,
-	types::{Actions, DataPayload, KeyPayload, ListPayload, NumberDataPayload},
-	SharedState,
-};
+use crate::pubsub::Subscriber;
+use crate::{error::ErrorCode, types::Actions, SharedState};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Payload {
@@ -33,6 +31,24 @@ pub struct WsResponse {
 	pub data: Option<serde_json::Value>,
 }
 
+impl WsResponse {
+	/// Response to request `id`, an error is only reported by its code.
+	pub fn new(id: u64, result: Result<serde_json::Value, Error>) -> Self {
+		match result {
+			Ok(data) => WsResponse {
+				id,
+				code: ErrorCode::Success as u64,
+				data: Some(data),
+			},
+			Err(error) => WsResponse {
+				id,
+				code: error.code,
+				data: None,
+			},
+		}
+	}
+}
+
 pub async fn handle_get(
 	ws: WebSocketUpgrade,
 	State(state): State<Arc<SharedState>>,
@@ -48,10 +64,40 @@ pub async fn handle_get(
 async fn handle_socket(mut socket: WebSocket, state: Arc<SharedState>) {
 	state.ws_connections.fetch_add(1, Ordering::AcqRel);
 
-	while let Some(Ok(msg)) = socket.next().await {
-		if process_message(&mut socket, msg, state.clone())
-			.await
-			.is_break()
+	let mut transaction: Transaction = Transaction::default();
+	let mut subscriber: Subscriber = Subscriber::new(state.clone());
+	loop {
+		let msg = tokio::select! {
+			msg = socket.next() => msg,
+			push = subscriber.next() => {
+				socket
+					.send(Message::Text(serde_json::to_string(&push).unwrap().into()))
+					.await
+					.ok();
+				continue;
+			}
+			_ = state.shutdown_requested() => {
+				let frame: CloseFrame = CloseFrame {
+					code: close_code::AWAY,
+					reason: "Server is shutting down".into(),
+				};
+				socket.send(Message::Close(Some(frame))).await.ok();
+				break;
+			}
+		};
+
+		let Some(Ok(msg)) = msg else {
+			break;
+		};
+		if process_message(
+			&mut socket,
+			msg,
+			state.clone(),
+			&mut transaction,
+			&mut subscriber,
+		)
+		.await
+		.is_break()
 		{
 			break;
 		}
@@ -64,128 +110,13 @@ async fn process_message(
 	socket: &mut WebSocket,
 	msg: Message,
 	state: Arc<SharedState>,
+	transaction: &mut Transaction,
+	subscriber: &mut Subscriber,
 ) -> ControlFlow<(), ()> {
 	match msg {
 		Message::Text(t) => {
-			if let Ok(payload) = serde_json::from_str::<Payload>(&t) {
-				let res: serde_json::Value = match payload.action {
-					Actions::PING => super::v1::ping::handle_ws(),
-					Actions::STATS => super::v1::stats::handle_ws(state),
-					Actions::SAVE => super::v1::save::handle_ws(state),
-					Actions::CLEAN => super::v1::clean::handle_ws(state),
-					Actions::FLUSH => super::v1::flush::handle_ws(state),
-					Actions::GET => {
-						if let Ok(data) = serde_json::from_value::<KeyPayload>(payload.data) {
-							super::v1::get::handle_ws(state, data.key)
-						} else {
-							serde_json::to_value(WsResponse {
-								id: payload.id,
-								code: ErrorCode::InvalidData as u64,
-								data: None,
-							})
-							.unwrap()
-						}
-					}
-					Actions::SET => {
-						if let Ok(data) = serde_json::from_value::<DataPayload>(payload.data) {
-							super::v1::set::handle_ws(state, data.key, data.value, data.ttl)
-						} else {
-							serde_json::to_value(WsResponse {
-								id: payload.id,
-								code: ErrorCode::InvalidData as u64,
-								data: None,
-							})
-							.unwrap()
-						}
-					}
-					Actions::DEL => {
-						if let Ok(data) = serde_json::from_value::<KeyPayload>(payload.data) {
-							super::v1::del::handle_ws(state, data.key)
-						} else {
-							serde_json::to_value(WsResponse {
-								id: payload.id,
-								code: ErrorCode::InvalidData as u64,
-								data: None,
-							})
-							.unwrap()
-						}
-					}
-					Actions::LIST => {
-						if let Ok(data) = serde_json::from_value::<ListPayload>(payload.data) {
-							super::v1::list::handle_ws(state, data.prefix, data.limit, data.cursor)
-						} else {
-							serde_json::to_value(WsResponse {
-								id: payload.id,
-								code: ErrorCode::InvalidData as u64,
-								data: None,
-							})
-							.unwrap()
-						}
-					}
-					Actions::EXISTS => {
-						if let Ok(data) = serde_json::from_value::<KeyPayload>(payload.data) {
-							super::v1::exists::handle_ws(state, data.key)
-						} else {
-							serde_json::to_value(WsResponse {
-								id: payload.id,
-								code: ErrorCode::InvalidData as u64,
-								data: None,
-							})
-							.unwrap()
-						}
-					}
-					Actions::INCR => {
-						if let Ok(data) = serde_json::from_value::<NumberDataPayload>(payload.data) {
-							super::v1::incr::handle_ws(state, data.key, data.value, data.ttl)
-						} else {
-							serde_json::to_value(WsResponse {
-								id: payload.id,
-								code: ErrorCode::InvalidData as u64,
-								data: None,
-							})
-							.unwrap()
-						}
-					}
-					Actions::DECR => {
-						if let Ok(data) = serde_json::from_value::<NumberDataPayload>(payload.data) {
-							super::v1::decr::handle_ws(state, data.key, data.value, data.ttl)
-						} else {
-							serde_json::to_value(WsResponse {
-								id: payload.id,
-								code: ErrorCode::InvalidData as u64,
-								data: None,
-							})
-							.unwrap()
-						}
-					}
-				};
-
-				let mut data: WsResponse = WsResponse {
-					id: payload.id,
-					code: 0,
-					data: None,
-				};
-
-				if let Some(code) = res.get("code").and_then(serde_json::Value::as_u64) {
-					data.code = code;
-				} else {
-					data.data = Some(res);
-				}
-
-				socket
-					.send(Message::Text(serde_json::to_string(&data).unwrap().into()))
-					.await
-					.ok();
-			} else {
-				socket
-					.send(Message::Text(
-						serde_json::to_string(&Error::from_code(ErrorCode::InvalidPayload))
-							.unwrap()
-							.into(),
-					))
-					.await
-					.ok();
-			}
+			let response: String = respond(&t, state, transaction, subscriber).await;
+			socket.send(Message::Text(response.into())).await.ok();
 		}
 
 		Message::Binary(d) => {
@@ -200,3 +131,117 @@ async fn process_message(
 	}
 	ControlFlow::Continue(())
 }
+
+/// Runs the request in `text` and returns the response to send back, the error
+/// itself when `text` is not a valid payload.
+async fn respond(
+	text: &str,
+	state: Arc<SharedState>,
+	transaction: &mut Transaction,
+	subscriber: &mut Subscriber,
+) -> String {
+	let Ok(payload) = serde_json::from_str::<Payload>(text) else {
+		return serde_json::to_string(&Error::from_code(ErrorCode::InvalidPayload)).unwrap();
+	};
+
+	let result: Result<serde_json::Value, Error> = match payload.action {
+		Actions::SUBSCRIBE | Actions::UNSUBSCRIBE | Actions::PSUBSCRIBE | Actions::PUNSUBSCRIBE => {
+			subscriber.execute(payload.action, payload.data)
+		}
+		action => {
+			transaction
+				.execute(state, payload.id, action, payload.data)
+				.await
+		}
+	};
+	serde_json::to_string(&WsResponse::new(payload.id, result)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::cache::Cache;
+	use crate::caches::eviction::Limits;
+	use crate::caches::snapshot::SnapshotOptions;
+
+	struct Session {
+		state: Arc<SharedState>,
+		transaction: Transaction,
+		subscriber: Subscriber,
+	}
+
+	impl Session {
+		fn new() -> Self {
+			let cache: Cache = Cache::new(
+				String::new(),
+				false,
+				SnapshotOptions::default(),
+				Limits::default(),
+				4,
+			);
+			let state: Arc<SharedState> = SharedState::for_test(cache);
+			Session {
+				subscriber: Subscriber::new(state.clone()),
+				transaction: Transaction::default(),
+				state,
+			}
+		}
+
+		async fn send(&mut self, request: serde_json::Value) -> serde_json::Value {
+			let response: String = respond(
+				&request.to_string(),
+				self.state.clone(),
+				&mut self.transaction,
+				&mut self.subscriber,
+			)
+			.await;
+			serde_json::from_str(&response).unwrap()
+		}
+	}
+
+	#[tokio::test]
+	async fn reports_invalid_data_by_its_code() {
+		let mut session: Session = Session::new();
+		let response = session
+			.send(serde_json::json!({ "id": 7, "action": "GET", "data": { "name": "a" } }))
+			.await;
+		assert_eq!(
+			response,
+			serde_json::json!({ "id": 7, "code": ErrorCode::InvalidData as u64, "data": null })
+		);
+	}
+
+	#[tokio::test]
+	async fn answers_invalid_payload_with_the_error() {
+		let mut session: Session = Session::new();
+		let response = session
+			.send(serde_json::json!({ "id": 7, "action": "NOPE" }))
+			.await;
+		assert_eq!(
+			response,
+			serde_json::to_value(Error::from_code(ErrorCode::InvalidPayload)).unwrap()
+		);
+	}
+
+	#[tokio::test]
+	async fn returns_hash_with_error_fields_as_data() {
+		let mut session: Session = Session::new();
+		let fields = serde_json::json!({ "code": 1011, "message": "not an error" });
+		let response = session
+			.send(serde_json::json!({
+				"id": 1,
+				"action": "HSET",
+				"data": { "key": "h", "fields": fields },
+			}))
+			.await;
+		assert_eq!(response["code"], 0);
+
+		let response = session
+			.send(serde_json::json!({ "id": 2, "action": "HGETALL", "data": { "key": "h" } }))
+			.await;
+		assert_eq!(
+			response,
+			serde_json::json!({ "id": 2, "code": 0, "data": fields })
+		);
+	}
+}
@@ -16,6 +16,15 @@ pub enum ErrorCode {
 	InvalidData = 1004,
 	InvalidPayload = 1005,
 	WriteToFile = 1006,
+	CompactionInProgress = 1007,
+	OutOfMemory = 1008,
+	WrongType = 1009,
+	InvalidFloat = 1010,
+	OutOfRange = 1011,
+	Conflict = 1012,
+	InvalidTransaction = 1013,
+	ScriptFailed = 1014,
+	UnknownScript = 1015,
 }
 
 impl ErrorCode {
@@ -29,6 +38,19 @@ impl ErrorCode {
 			ErrorCode::InvalidData => "Invalid data!".to_string(),
 			ErrorCode::InvalidPayload => "Invalid payload!".to_string(),
 			ErrorCode::WriteToFile => "Failed to save data to file!".to_string(),
+			ErrorCode::CompactionInProgress => "Compaction is already in progress!".to_string(),
+			ErrorCode::OutOfMemory => {
+				"Memory or key limit reached and no key can be evicted!".to_string()
+			}
+			ErrorCode::WrongType => "Key holds a value of another type!".to_string(),
+			ErrorCode::InvalidFloat => "Value is not a finite number!".to_string(),
+			ErrorCode::OutOfRange => "Result is outside of the allowed range!".to_string(),
+			ErrorCode::Conflict => "Key was changed in the meantime!".to_string(),
+			ErrorCode::InvalidTransaction => {
+				"Action is not allowed at this point of a transaction!".to_string()
+			}
+			ErrorCode::ScriptFailed => "Script failed!".to_string(),
+			ErrorCode::UnknownScript => "No script was loaded with this hash!".to_string(),
 		}
 	}
 }
@@ -1,44 +1,121 @@
 use axum::{
+	middleware,
 	routing::{get, post},
 	Router,
 };
 use clap::Parser;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
-use std::{fs, sync::atomic::AtomicU64};
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 
 use crate::tcp::{authenticate, handle_client};
 
 pub mod caches;
 pub mod error;
+pub mod pubsub;
+pub mod scripts;
 pub mod state;
+pub mod tasks;
 pub mod tcp;
 pub mod types;
 pub mod utils;
 mod endpoints {
+	pub mod dispatch;
 	pub mod metrics;
+	pub mod transaction;
 	pub mod ws;
 	pub mod v1 {
+		pub mod blpop;
+		pub mod cas;
 		pub mod clean;
+		pub mod compact;
 		pub mod decr;
+		pub mod decrbyfloat;
 		pub mod del;
+		pub mod eval;
+		pub mod evalsha;
+		pub mod exec;
 		pub mod exists;
+		pub mod expire;
+		pub mod expireat;
+		pub mod expiring;
 		pub mod flush;
 		pub mod get;
+		pub mod getset;
+		pub mod hdel;
 		pub mod health;
+		pub mod hexists;
+		pub mod hget;
+		pub mod hgetall;
+		pub mod hincrby;
+		pub mod hkeys;
+		pub mod hlen;
+		pub mod hset;
 		pub mod incr;
+		pub mod incrbyfloat;
+		pub mod lindex;
 		pub mod list;
+		pub mod llen;
+		pub mod lpop;
+		pub mod lpush;
+		pub mod lrange;
+		pub mod ltrim;
+		pub mod mdel;
+		pub mod mexists;
+		pub mod mget;
+		pub mod mset;
+		pub mod persist;
+		pub mod pexpire;
 		pub mod ping;
+		pub mod poll;
+		pub mod pttl;
+		pub mod publish;
+		pub mod rpop;
+		pub mod rpush;
+		pub mod sadd;
 		pub mod save;
+		pub mod scard;
+		pub mod scriptload;
+		pub mod sdiff;
 		pub mod set;
+		pub mod setnx;
+		pub mod setxx;
+		pub mod sinter;
+		pub mod sismember;
+		pub mod smembers;
+		pub mod spop;
+		pub mod srandmember;
+		pub mod srem;
 		pub mod stats;
+		pub mod sunion;
+		pub mod ttl;
+		pub mod watch;
+		pub mod zadd;
+		pub mod zcard;
+		pub mod zincrby;
+		pub mod zrange;
+		pub mod zrangebyscore;
+		pub mod zrank;
+		pub mod zrem;
+		pub mod zremrangebyscore;
+		pub mod zscore;
 	}
 }
 
+use crate::caches::aof::FsyncPolicy;
 use crate::caches::cache::Cache;
+use crate::caches::eviction::{EvictionPolicy, Limits};
+use crate::caches::snapshot::{SnapshotFormat, SnapshotOptions};
+use crate::pubsub::PubSub;
+use crate::scripts::Scripts;
 use state::SharedState;
+use tasks::SaveRule;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -62,28 +139,119 @@ struct Args {
 	/// Preserve items relative order
 	#[arg(long, default_value_t = false)]
 	preserve_order: bool,
+
+	/// Encoding used when writing snapshots, existing ones are detected automatically
+	#[arg(long, value_enum, default_value_t = SnapshotFormat::Json)]
+	snapshot_format: SnapshotFormat,
+
+	/// Compress snapshots with LZ4
+	#[arg(long, default_value_t = false)]
+	snapshot_compress: bool,
+
+	/// Log every write to an append-only file that is replayed on startup
+	#[arg(long, default_value_t = false)]
+	appendonly: bool,
+
+	/// How often the append-only file is flushed to disk
+	#[arg(long, value_enum, default_value_t = FsyncPolicy::Everysec)]
+	appendfsync: FsyncPolicy,
+
+	/// Compact the append-only file once it reaches this percentage of the snapshot size
+	#[arg(long, default_value_t = 100)]
+	aof_rewrite_percentage: u64,
+
+	/// Minimum append-only file size in bytes before it is compacted automatically
+	#[arg(long, default_value_t = 64 * 1024 * 1024)]
+	aof_rewrite_min_size: u64,
+
+	/// Save a snapshot when at least <writes> changes happened in <seconds> (e.g. --save 300:10, repeatable)
+	#[arg(long, value_name = "SECONDS:WRITES")]
+	save: Vec<SaveRule>,
+
+	/// Estimated memory limit in bytes, 0 for unlimited
+	#[arg(long, default_value_t = 0)]
+	max_memory: u64,
+
+	/// Maximum number of keys, 0 for unlimited
+	#[arg(long, default_value_t = 0)]
+	max_keys: usize,
+
+	/// What to do when a write would exceed --max-memory or --max-keys
+	#[arg(long, value_enum, default_value_t = EvictionPolicy::Noeviction)]
+	eviction_policy: EvictionPolicy,
+
+	/// Number of independently locked parts the keyspace is split into
+	#[arg(long, default_value_t = 16)]
+	shards: usize,
+
+	/// Milliseconds a script may run before it is stopped
+	#[arg(long, default_value_t = 5000)]
+	script_timeout: u64,
+
+	/// Publish changes to keys on the `__keyspace__:<key>` and `__keyevent__:<event>` channels
+	#[arg(long, default_value_t = false)]
+	notify_keyspace_events: bool,
+
+	/// Seconds to wait for open connections to finish when shutting down
+	#[arg(long, default_value_t = 10)]
+	shutdown_timeout: u64,
 }
 
 #[tokio::main]
 async fn main() {
 	let args: Args = Args::parse();
 
+	let cache: Cache = Cache::new(
+		args.path.clone(),
+		args.preserve_order,
+		SnapshotOptions {
+			format: args.snapshot_format,
+			compress: args.snapshot_compress,
+		},
+		Limits {
+			max_memory: args.max_memory,
+			max_keys: args.max_keys,
+			policy: args.eviction_policy,
+		},
+		args.shards,
+	);
 	let state: Arc<SharedState> = Arc::new(SharedState {
 		token: args.token.clone(),
 		ws_connections: AtomicU64::new(0),
-		cache: Mutex::new(Cache::new(args.path.clone(), args.preserve_order)),
+		compacting: AtomicBool::new(false),
+		shutdown: watch::channel(false).0,
+		scripts: Scripts::new(Duration::from_millis(args.script_timeout)),
+		pubsub: PubSub::default(),
+		cache,
 	});
 
-	let file = args.path.clone() + "/cache.json";
-	let path = Path::new(&file);
-	if !path.exists() {
-		fs::create_dir_all(&args.path).expect("Failed with creating cache.json file!");
-		fs::write(&file, "{}").expect("Failed with creating cache.json file!");
+	fs::create_dir_all(&args.path).expect("Failed to create cache directory!");
+	if let Err(err) = state.cache.load() {
+		eprintln!("Failed to load cache from {}: {}", args.path, err);
+		eprintln!("Refusing to start with an empty cache, fix or remove the files above first");
+		std::process::exit(1);
+	}
+	if args.appendonly {
+		state
+			.cache
+			.open_aof(args.appendfsync)
+			.expect("Failed to open append-only file!");
+	}
+
+	tokio::spawn(tasks::fsync_aof(state.clone()));
+	tokio::spawn(tasks::auto_compact(
+		state.clone(),
+		args.aof_rewrite_percentage,
+		args.aof_rewrite_min_size,
+	));
+	tokio::spawn(tasks::auto_save(state.clone(), args.save.clone()));
+	tokio::spawn(tasks::active_expire(state.clone()));
+	if args.notify_keyspace_events {
+		tokio::spawn(tasks::keyspace_notifications(state.clone()));
 	}
-	state.clone().cache.lock().unwrap().load().ok();
 
-	let address: String = args.address.clone() + ":" + &args.port.to_string();
-	let tcp_address: String = args.address.clone() + ":" + &(args.port + 1).to_string();
+	let address: String = format!("{}:{}", args.address, args.port);
+	let tcp_address: String = format!("{}:{}", args.address, args.port + 1);
 
 	let app: Router = Router::new()
 		.route("/ws/{token}", get(endpoints::ws::handle_get))
@@ -101,6 +269,11 @@ async fn main() {
 			"/v1/list/{prefix}/{limit}/{cursor}",
 			get(endpoints::v1::list::handle_get),
 		)
+		.route("/v1/expiring", post(endpoints::v1::expiring::handle_post))
+		.route(
+			"/v1/expiring/{seconds}/{limit}",
+			get(endpoints::v1::expiring::handle_get),
+		)
 		.route("/v1/incr", post(endpoints::v1::incr::handle_post))
 		.route(
 			"/v1/incr/{key}/{value}/{ttl}",
@@ -115,19 +288,257 @@ async fn main() {
 		.route("/v1/get/{key}", get(endpoints::v1::get::handle_get))
 		.route("/v1/exists", post(endpoints::v1::exists::handle_post))
 		.route("/v1/exists/{key}", get(endpoints::v1::exists::handle_get))
+		.route("/v1/ttl", post(endpoints::v1::ttl::handle_post))
+		.route("/v1/ttl/{key}", get(endpoints::v1::ttl::handle_get))
+		.route("/v1/pttl", post(endpoints::v1::pttl::handle_post))
+		.route("/v1/pttl/{key}", get(endpoints::v1::pttl::handle_get))
+		.route("/v1/expire", post(endpoints::v1::expire::handle_post))
+		.route(
+			"/v1/expire/{key}/{ttl}",
+			get(endpoints::v1::expire::handle_get),
+		)
+		.route("/v1/pexpire", post(endpoints::v1::pexpire::handle_post))
+		.route(
+			"/v1/pexpire/{key}/{ttl}",
+			get(endpoints::v1::pexpire::handle_get),
+		)
+		.route("/v1/expireat", post(endpoints::v1::expireat::handle_post))
+		.route(
+			"/v1/expireat/{key}/{timestamp}",
+			get(endpoints::v1::expireat::handle_get),
+		)
+		.route("/v1/persist", post(endpoints::v1::persist::handle_post))
+		.route("/v1/persist/{key}", get(endpoints::v1::persist::handle_get))
+		.route("/v1/hset", post(endpoints::v1::hset::handle_post))
+		.route(
+			"/v1/hset/{key}/{field}/{value}",
+			get(endpoints::v1::hset::handle_get),
+		)
+		.route("/v1/hget", post(endpoints::v1::hget::handle_post))
+		.route(
+			"/v1/hget/{key}/{field}",
+			get(endpoints::v1::hget::handle_get),
+		)
+		.route("/v1/hdel", post(endpoints::v1::hdel::handle_post))
+		.route(
+			"/v1/hdel/{key}/{field}",
+			get(endpoints::v1::hdel::handle_get),
+		)
+		.route("/v1/hincrby", post(endpoints::v1::hincrby::handle_post))
+		.route(
+			"/v1/hincrby/{key}/{field}/{value}",
+			get(endpoints::v1::hincrby::handle_get),
+		)
+		.route("/v1/hexists", post(endpoints::v1::hexists::handle_post))
+		.route(
+			"/v1/hexists/{key}/{field}",
+			get(endpoints::v1::hexists::handle_get),
+		)
+		.route("/v1/hgetall", post(endpoints::v1::hgetall::handle_post))
+		.route("/v1/hgetall/{key}", get(endpoints::v1::hgetall::handle_get))
+		.route("/v1/hlen", post(endpoints::v1::hlen::handle_post))
+		.route("/v1/hlen/{key}", get(endpoints::v1::hlen::handle_get))
+		.route("/v1/hkeys", post(endpoints::v1::hkeys::handle_post))
+		.route("/v1/hkeys/{key}", get(endpoints::v1::hkeys::handle_get))
+		.route("/v1/lpush", post(endpoints::v1::lpush::handle_post))
+		.route(
+			"/v1/lpush/{key}/{value}",
+			get(endpoints::v1::lpush::handle_get),
+		)
+		.route("/v1/rpush", post(endpoints::v1::rpush::handle_post))
+		.route(
+			"/v1/rpush/{key}/{value}",
+			get(endpoints::v1::rpush::handle_get),
+		)
+		.route("/v1/lpop", post(endpoints::v1::lpop::handle_post))
+		.route("/v1/lpop/{key}", get(endpoints::v1::lpop::handle_get))
+		.route("/v1/rpop", post(endpoints::v1::rpop::handle_post))
+		.route("/v1/rpop/{key}", get(endpoints::v1::rpop::handle_get))
+		.route("/v1/lrange", post(endpoints::v1::lrange::handle_post))
+		.route(
+			"/v1/lrange/{key}/{start}/{stop}",
+			get(endpoints::v1::lrange::handle_get),
+		)
+		.route("/v1/llen", post(endpoints::v1::llen::handle_post))
+		.route("/v1/llen/{key}", get(endpoints::v1::llen::handle_get))
+		.route("/v1/ltrim", post(endpoints::v1::ltrim::handle_post))
+		.route(
+			"/v1/ltrim/{key}/{start}/{stop}",
+			get(endpoints::v1::ltrim::handle_get),
+		)
+		.route("/v1/lindex", post(endpoints::v1::lindex::handle_post))
+		.route(
+			"/v1/lindex/{key}/{index}",
+			get(endpoints::v1::lindex::handle_get),
+		)
+		.route("/v1/sadd", post(endpoints::v1::sadd::handle_post))
+		.route(
+			"/v1/sadd/{key}/{member}",
+			get(endpoints::v1::sadd::handle_get),
+		)
+		.route("/v1/srem", post(endpoints::v1::srem::handle_post))
+		.route(
+			"/v1/srem/{key}/{member}",
+			get(endpoints::v1::srem::handle_get),
+		)
+		.route("/v1/sismember", post(endpoints::v1::sismember::handle_post))
+		.route(
+			"/v1/sismember/{key}/{member}",
+			get(endpoints::v1::sismember::handle_get),
+		)
+		.route("/v1/smembers", post(endpoints::v1::smembers::handle_post))
+		.route(
+			"/v1/smembers/{key}",
+			get(endpoints::v1::smembers::handle_get),
+		)
+		.route("/v1/scard", post(endpoints::v1::scard::handle_post))
+		.route("/v1/scard/{key}", get(endpoints::v1::scard::handle_get))
+		.route("/v1/spop", post(endpoints::v1::spop::handle_post))
+		.route("/v1/spop/{key}", get(endpoints::v1::spop::handle_get))
+		.route(
+			"/v1/srandmember",
+			post(endpoints::v1::srandmember::handle_post),
+		)
+		.route(
+			"/v1/srandmember/{key}/{count}",
+			get(endpoints::v1::srandmember::handle_get),
+		)
+		.route("/v1/sinter", post(endpoints::v1::sinter::handle_post))
+		.route("/v1/sunion", post(endpoints::v1::sunion::handle_post))
+		.route("/v1/sdiff", post(endpoints::v1::sdiff::handle_post))
+		.route("/v1/zadd", post(endpoints::v1::zadd::handle_post))
+		.route(
+			"/v1/zadd/{key}/{member}/{score}",
+			get(endpoints::v1::zadd::handle_get),
+		)
+		.route("/v1/zincrby", post(endpoints::v1::zincrby::handle_post))
+		.route(
+			"/v1/zincrby/{key}/{member}/{value}",
+			get(endpoints::v1::zincrby::handle_get),
+		)
+		.route("/v1/zscore", post(endpoints::v1::zscore::handle_post))
+		.route(
+			"/v1/zscore/{key}/{member}",
+			get(endpoints::v1::zscore::handle_get),
+		)
+		.route("/v1/zrank", post(endpoints::v1::zrank::handle_post))
+		.route(
+			"/v1/zrank/{key}/{member}",
+			get(endpoints::v1::zrank::handle_get),
+		)
+		.route("/v1/zrange", post(endpoints::v1::zrange::handle_post))
+		.route(
+			"/v1/zrange/{key}/{start}/{stop}",
+			get(endpoints::v1::zrange::handle_get),
+		)
+		.route(
+			"/v1/zrangebyscore",
+			post(endpoints::v1::zrangebyscore::handle_post),
+		)
+		.route(
+			"/v1/zrangebyscore/{key}/{min}/{max}",
+			get(endpoints::v1::zrangebyscore::handle_get),
+		)
+		.route("/v1/zrem", post(endpoints::v1::zrem::handle_post))
+		.route(
+			"/v1/zrem/{key}/{member}",
+			get(endpoints::v1::zrem::handle_get),
+		)
+		.route(
+			"/v1/zremrangebyscore",
+			post(endpoints::v1::zremrangebyscore::handle_post),
+		)
+		.route(
+			"/v1/zremrangebyscore/{key}/{min}/{max}",
+			get(endpoints::v1::zremrangebyscore::handle_get),
+		)
+		.route("/v1/zcard", post(endpoints::v1::zcard::handle_post))
+		.route("/v1/zcard/{key}", get(endpoints::v1::zcard::handle_get))
+		.route(
+			"/v1/incrbyfloat",
+			post(endpoints::v1::incrbyfloat::handle_post),
+		)
+		.route(
+			"/v1/incrbyfloat/{key}/{value}/{ttl}",
+			get(endpoints::v1::incrbyfloat::handle_get),
+		)
+		.route(
+			"/v1/decrbyfloat",
+			post(endpoints::v1::decrbyfloat::handle_post),
+		)
+		.route(
+			"/v1/decrbyfloat/{key}/{value}/{ttl}",
+			get(endpoints::v1::decrbyfloat::handle_get),
+		)
+		.route("/v1/setnx", post(endpoints::v1::setnx::handle_post))
+		.route(
+			"/v1/setnx/{key}/{value}/{ttl}",
+			get(endpoints::v1::setnx::handle_get),
+		)
+		.route("/v1/setxx", post(endpoints::v1::setxx::handle_post))
+		.route(
+			"/v1/setxx/{key}/{value}/{ttl}",
+			get(endpoints::v1::setxx::handle_get),
+		)
+		.route("/v1/getset", post(endpoints::v1::getset::handle_post))
+		.route(
+			"/v1/getset/{key}/{value}/{ttl}",
+			get(endpoints::v1::getset::handle_get),
+		)
+		.route("/v1/cas", post(endpoints::v1::cas::handle_post))
+		.route("/v1/mget", post(endpoints::v1::mget::handle_post))
+		.route("/v1/mset", post(endpoints::v1::mset::handle_post))
+		.route("/v1/mdel", post(endpoints::v1::mdel::handle_post))
+		.route("/v1/mexists", post(endpoints::v1::mexists::handle_post))
+		.route(
+			"/v1/scriptload",
+			post(endpoints::v1::scriptload::handle_post),
+		)
+		.route("/v1/publish", post(endpoints::v1::publish::handle_post))
 		.route("/v1/save", get(endpoints::v1::save::handle_get))
+		.route("/v1/compact", get(endpoints::v1::compact::handle_get))
 		.route("/v1/clean", get(endpoints::v1::clean::handle_get))
 		.route("/v1/flush", get(endpoints::v1::flush::handle_get))
 		.route("/v1/stats", get(endpoints::v1::stats::handle_get))
 		.route("/v1/ping", get(endpoints::v1::ping::handle_get))
+		.route_layer(middleware::from_fn_with_state(
+			state.clone(),
+			endpoints::transaction::isolate,
+		))
+		// These take the transaction lock themselves, so they must not run as an operation.
+		.route("/v1/exec", post(endpoints::v1::exec::handle_post))
+		.route("/v1/eval", post(endpoints::v1::eval::handle_post))
+		.route("/v1/evalsha", post(endpoints::v1::evalsha::handle_post))
+		// These wait for changes, holding the lock meanwhile would stall transactions.
+		.route("/v1/poll", post(endpoints::v1::poll::handle_post))
+		.route(
+			"/v1/poll/{key}/{version}/{timeout}",
+			get(endpoints::v1::poll::handle_get),
+		)
+		.route(
+			"/v1/watch",
+			get(endpoints::v1::watch::handle_get).post(endpoints::v1::watch::handle_post),
+		)
+		.route(
+			"/v1/watch/key/{key}",
+			get(endpoints::v1::watch::handle_get_key),
+		)
+		.route(
+			"/v1/watch/prefix/{prefix}",
+			get(endpoints::v1::watch::handle_get_prefix),
+		)
 		.with_state(state.clone());
 
-	tokio::spawn(async move {
+	let http_state: Arc<SharedState> = state.clone();
+	let http_server = tokio::spawn(async move {
 		let listener: TcpListener = TcpListener::bind(&address)
 			.await
 			.expect("Failed to bind HTTP listener");
 		println!("HTTP Server is running on {}", &address);
-		axum::serve(listener, app).await.unwrap();
+		axum::serve(listener, app)
+			.with_graceful_shutdown(async move { http_state.shutdown_requested().await })
+			.await
+			.unwrap();
 	});
 
 	let tcp_listener: TcpListener = TcpListener::bind(&tcp_address)
@@ -135,22 +546,36 @@ async fn main() {
 		.expect("Failed to bind TCP listener");
 	println!("TCP Server is running on {}", &tcp_address);
 
+	let signal_state: Arc<SharedState> = state.clone();
+	tokio::spawn(async move {
+		shutdown_signal().await;
+		println!("Shutting down, no longer accepting connections");
+		signal_state.shutdown.send_replace(true);
+	});
+
+	let mut tcp_clients: JoinSet<()> = JoinSet::new();
 	loop {
-		match tcp_listener.accept().await {
+		let accepted = tokio::select! {
+			accepted = tcp_listener.accept() => accepted,
+			_ = state.shutdown_requested() => break,
+		};
+		while tcp_clients.try_join_next().is_some() {}
+
+		match accepted {
 			Ok((mut stream, addr)) => {
 				println!("New TCP connection: {}", addr);
 				let token_clone: String = args.token.clone();
 				let state_clone: Arc<SharedState> = state.clone();
 
-				tokio::spawn(async move {
+				tcp_clients.spawn(async move {
 					if authenticate(&mut stream, &token_clone).await {
 						handle_client(&mut stream, state_clone).await;
 					} else {
 						println!("Authentication failed for {}", addr);
+					}
 
-						if let Err(e) = stream.shutdown().await {
-							eprintln!("Error shutting down connection for {}: {}", addr, e);
-						}
+					if let Err(e) = stream.shutdown().await {
+						eprintln!("Error shutting down connection for {}: {}", addr, e);
 					}
 				});
 			}
@@ -159,4 +584,64 @@ async fn main() {
 			}
 		}
 	}
+	drop(tcp_listener);
+
+	let deadline: Instant = Instant::now() + Duration::from_secs(args.shutdown_timeout);
+	let drained: bool = tokio::time::timeout_at(deadline, async {
+		http_server.await.ok();
+		while tcp_clients.join_next().await.is_some() {}
+		while state.ws_connections.load(Ordering::Acquire) > 0 {
+			tokio::time::sleep(Duration::from_millis(50)).await;
+		}
+	})
+	.await
+	.is_ok();
+	if !drained {
+		eprintln!(
+			"Connections did not close within {} seconds, closing them forcefully",
+			args.shutdown_timeout
+		);
+		tcp_clients.abort_all();
+	}
+
+	if let Some(aof) = &mut state.cache.persistence().aof {
+		if let Err(err) = aof.sync() {
+			eprintln!("Failed to fsync append-only log: {}", err);
+		}
+	}
+	match state.cache.save() {
+		Ok(_) => {
+			println!("Cache saved to {}", args.path);
+			std::process::exit(0);
+		}
+		Err(err) => {
+			eprintln!("Failed to save cache to {}: {}", args.path, err);
+			std::process::exit(1);
+		}
+	}
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM (sent by `docker stop`) on Unix.
+async fn shutdown_signal() {
+	let ctrl_c = async {
+		tokio::signal::ctrl_c()
+			.await
+			.expect("Failed to install Ctrl+C handler");
+	};
+
+	#[cfg(unix)]
+	let terminate = async {
+		tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+			.expect("Failed to install SIGTERM handler")
+			.recv()
+			.await;
+	};
+
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<()>();
+
+	tokio::select! {
+		_ = ctrl_c => {},
+		_ = terminate => {},
+	}
 }
@@ -0,0 +1,313 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+use crate::error::{Error, ErrorCode};
+use crate::types::{Actions, ChannelsPayload};
+use crate::SharedState;
+
+/// Messages waiting to be sent to a single subscriber. When a subscriber falls
+/// this far behind, new messages are dropped for it until it catches up.
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+/// A published message as pushed to a subscriber. Pushes are the only frames
+/// with a `push` field at the top, responses to actions are `WsResponse` frames
+/// on both WebSocket and TCP.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushMessage {
+	/// "message" for channel subscriptions, "pmessage" for pattern subscriptions
+	pub push: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub pattern: Option<String>,
+	pub channel: String,
+	pub message: serde_json::Value,
+}
+
+type Subscribers = HashMap<u64, mpsc::Sender<PushMessage>>;
+
+/// Channels and glob patterns with the subscribers listening to them.
+#[derive(Default)]
+pub struct PubSub {
+	next_id: AtomicU64,
+	channels: Mutex<HashMap<String, Subscribers>>,
+	patterns: Mutex<HashMap<String, Subscribers>>,
+}
+
+impl PubSub {
+	/// Sends `message` to everyone subscribed to `channel` or to a pattern matching
+	/// it, and returns how many subscriptions it was delivered to.
+	pub fn publish(&self, channel: &str, message: serde_json::Value) -> usize {
+		let mut delivered: usize = 0;
+		if let Some(subscribers) = self.channels.lock().unwrap().get(channel) {
+			let push: PushMessage = PushMessage {
+				push: "message".to_string(),
+				pattern: None,
+				channel: channel.to_string(),
+				message: message.clone(),
+			};
+			delivered += send(subscribers, &push);
+		}
+		for (pattern, subscribers) in self.patterns.lock().unwrap().iter() {
+			if glob_match(pattern.as_bytes(), channel.as_bytes()) {
+				let push: PushMessage = PushMessage {
+					push: "pmessage".to_string(),
+					pattern: Some(pattern.clone()),
+					channel: channel.to_string(),
+					message: message.clone(),
+				};
+				delivered += send(subscribers, &push);
+			}
+		}
+		delivered
+	}
+}
+
+fn send(subscribers: &Subscribers, push: &PushMessage) -> usize {
+	subscribers
+		.values()
+		.filter(|sender| sender.try_send(push.clone()).is_ok())
+		.count()
+}
+
+fn add(
+	map: &Mutex<HashMap<String, Subscribers>>,
+	name: &str,
+	id: u64,
+	sender: &mpsc::Sender<PushMessage>,
+) {
+	map
+		.lock()
+		.unwrap()
+		.entry(name.to_string())
+		.or_default()
+		.insert(id, sender.clone());
+}
+
+fn remove(map: &Mutex<HashMap<String, Subscribers>>, name: &str, id: u64) {
+	let mut map = map.lock().unwrap();
+	if let Some(subscribers) = map.get_mut(name) {
+		subscribers.remove(&id);
+		if subscribers.is_empty() {
+			map.remove(name);
+		}
+	}
+}
+
+/// Subscriptions of a WebSocket or TCP session, removed when it is dropped.
+pub struct Subscriber {
+	state: Arc<SharedState>,
+	id: u64,
+	sender: mpsc::Sender<PushMessage>,
+	receiver: mpsc::Receiver<PushMessage>,
+	channels: HashSet<String>,
+	patterns: HashSet<String>,
+}
+
+impl Subscriber {
+	pub fn new(state: Arc<SharedState>) -> Self {
+		let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+		Subscriber {
+			id: state.pubsub.next_id.fetch_add(1, Ordering::Relaxed),
+			state,
+			sender,
+			receiver,
+			channels: HashSet::new(),
+			patterns: HashSet::new(),
+		}
+	}
+
+	/// Handles SUBSCRIBE, UNSUBSCRIBE, PSUBSCRIBE and PUNSUBSCRIBE. Unsubscribing
+	/// from an empty list removes all subscriptions of that kind.
+	/// Returns how many subscriptions the session has left.
+	pub fn execute(
+		&mut self,
+		action: Actions,
+		data: serde_json::Value,
+	) -> Result<serde_json::Value, Error> {
+		let pubsub: &PubSub = &self.state.pubsub;
+		let (map, names, subscribe) = match action {
+			Actions::SUBSCRIBE => (&pubsub.channels, &mut self.channels, true),
+			Actions::UNSUBSCRIBE => (&pubsub.channels, &mut self.channels, false),
+			Actions::PSUBSCRIBE => (&pubsub.patterns, &mut self.patterns, true),
+			Actions::PUNSUBSCRIBE => (&pubsub.patterns, &mut self.patterns, false),
+			_ => return Err(Error::from_code(ErrorCode::InvalidData)),
+		};
+		let Ok(data) = serde_json::from_value::<ChannelsPayload>(data) else {
+			return Err(Error::from_code(ErrorCode::InvalidData));
+		};
+
+		if subscribe {
+			for name in data.channels {
+				add(map, &name, self.id, &self.sender);
+				names.insert(name);
+			}
+		} else {
+			let removed: Vec<String> = if data.channels.is_empty() {
+				names.drain().collect()
+			} else {
+				data
+					.channels
+					.into_iter()
+					.filter(|name| names.remove(name))
+					.collect()
+			};
+			for name in removed {
+				remove(map, &name, self.id);
+			}
+		}
+		Ok(serde_json::Value::from(
+			self.channels.len() + self.patterns.len(),
+		))
+	}
+
+	/// Waits for the next message to push to the session.
+	pub async fn next(&mut self) -> PushMessage {
+		// The subscriber keeps a sender itself, so the channel is never closed.
+		self.receiver.recv().await.unwrap()
+	}
+}
+
+impl Drop for Subscriber {
+	fn drop(&mut self) {
+		for channel in &self.channels {
+			remove(&self.state.pubsub.channels, channel, self.id);
+		}
+		for pattern in &self.patterns {
+			remove(&self.state.pubsub.patterns, pattern, self.id);
+		}
+	}
+}
+
+/// Matches `text` against a glob `pattern`: `*` matches any sequence, `?` any single
+/// byte, `[abc]` and `[a-z]` a byte in the set (`[^...]` one that is not), and `\`
+/// escapes the next byte.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+	let (mut p, mut t) = (0, 0);
+	// Where to resume after the last `*` when the rest does not match.
+	let mut star: Option<(usize, usize)> = None;
+
+	while t < text.len() {
+		if p < pattern.len() && pattern[p] == b'*' {
+			star = Some((p + 1, t));
+			p += 1;
+			continue;
+		}
+		if let Some(next) = match_one(pattern, p, text[t]) {
+			p = next;
+			t += 1;
+			continue;
+		}
+		match star {
+			Some((star_p, star_t)) => {
+				p = star_p;
+				t = star_t + 1;
+				star = Some((star_p, star_t + 1));
+			}
+			None => return false,
+		}
+	}
+	pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the pattern element at `p`, returns where the next one starts.
+fn match_one(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+	match *pattern.get(p)? {
+		b'?' => Some(p + 1),
+		b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+		b'[' => {
+			let mut i: usize = p + 1;
+			let negated: bool = matches!(pattern.get(i), Some(b'^' | b'!'));
+			if negated {
+				i += 1;
+			}
+			let mut matched: bool = false;
+			let mut first: bool = true;
+			while i < pattern.len() && (first || pattern[i] != b']') {
+				first = false;
+				let low: u8 = pattern[i];
+				if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+					matched |= (low..=pattern[i + 2]).contains(&c);
+					i += 3;
+				} else {
+					matched |= low == c;
+					i += 1;
+				}
+			}
+			// An unclosed `[` is taken literally.
+			if i >= pattern.len() {
+				return (c == b'[').then_some(p + 1);
+			}
+			(matched != negated).then_some(i + 1)
+		}
+		literal => (literal == c).then_some(p + 1),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::glob_match;
+
+	fn matches(pattern: &str, text: &str) -> bool {
+		glob_match(pattern.as_bytes(), text.as_bytes())
+	}
+
+	#[test]
+	fn matches_literals() {
+		assert!(matches("", ""));
+		assert!(!matches("", "a"));
+		assert!(!matches("a", ""));
+		assert!(matches("user:1", "user:1"));
+		assert!(!matches("user:1", "user:12"));
+	}
+
+	#[test]
+	fn matches_wildcards() {
+		assert!(matches("*", ""));
+		assert!(matches("*", "anything"));
+		assert!(matches("**", "a"));
+		assert!(matches("user:*", "user:"));
+		assert!(matches("user:*", "user:1:name"));
+		assert!(matches("*:name", "user:1:name"));
+		assert!(matches("a*b*c", "aXbYbZc"));
+		assert!(!matches("a*b*c", "aXbYbZ"));
+		assert!(matches("a*", "a"));
+		assert!(matches("?", "x"));
+		assert!(!matches("?", ""));
+		assert!(!matches("?", "xy"));
+		assert!(matches("h?llo", "hallo"));
+		assert!(matches("*?", "x"));
+		assert!(!matches("*?", ""));
+	}
+
+	#[test]
+	fn matches_classes() {
+		assert!(matches("h[ae]llo", "hello"));
+		assert!(!matches("h[ae]llo", "hillo"));
+		assert!(matches("[a-c]x", "bx"));
+		assert!(!matches("[a-c]x", "dx"));
+		assert!(matches("[0-9a-f]", "e"));
+		assert!(matches("[^a-c]", "d"));
+		assert!(!matches("[^a-c]", "b"));
+		assert!(matches("[!a]", "b"));
+		assert!(!matches("[!a]", "a"));
+		// A `]` right after the opening bracket is a member.
+		assert!(matches("[]a]", "]"));
+		// A `-` right before the closing bracket is a member.
+		assert!(matches("[a-]", "-"));
+	}
+
+	#[test]
+	fn matches_escapes_and_unclosed_classes() {
+		assert!(matches("a\\*", "a*"));
+		assert!(!matches("a\\*", "ab"));
+		assert!(matches("\\?", "?"));
+		assert!(!matches("\\?", "x"));
+		assert!(matches("\\[a]", "[a]"));
+		// A trailing backslash is a literal.
+		assert!(matches("a\\", "a\\"));
+		assert!(matches("[ab", "[ab"));
+		assert!(!matches("[ab", "a"));
+	}
+}
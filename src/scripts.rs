@@ -0,0 +1,219 @@
+use rhai::{Dynamic, Engine, EvalAltResult, AST};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::endpoints::dispatch;
+use crate::error::{Error, ErrorCode};
+use crate::state::Scope;
+use crate::types::Actions;
+use crate::SharedState;
+
+/// Largest string a script can build, in bytes.
+const MAX_STRING_SIZE: usize = 1 << 20;
+/// Most elements of an array or a map a script can build.
+const MAX_COLLECTION_SIZE: usize = 10_000;
+/// Deepest nesting of function calls.
+const MAX_CALL_LEVELS: usize = 64;
+/// Deepest nesting of expressions, at the top level and inside functions.
+const MAX_EXPR_DEPTHS: (usize, usize) = (64, 32);
+/// Most operations a single run may take, on top of the time limit.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+/// Compiled Rhai scripts by the SHA1 hash of their source, kept until restart.
+pub struct Scripts {
+	scripts: RwLock<HashMap<String, Arc<AST>>>,
+	/// How long a single script may run before it is stopped.
+	pub timeout: Duration,
+}
+
+impl Scripts {
+	pub fn new(timeout: Duration) -> Self {
+		Scripts {
+			scripts: RwLock::new(HashMap::new()),
+			timeout,
+		}
+	}
+
+	/// Compiles `source` and keeps it under its hash, which is returned.
+	pub fn load(&self, source: &str) -> Result<(String, Arc<AST>), Error> {
+		let hash: String = sha1_smol::Sha1::from(source).digest().to_string();
+		if let Some(script) = self.get(&hash) {
+			return Ok((hash, script));
+		}
+
+		let script: Arc<AST> = Arc::new(
+			engine()
+				.compile(source)
+				.map_err(|err| script_failed(&err.to_string()))?,
+		);
+		self
+			.scripts
+			.write()
+			.unwrap()
+			.insert(hash.clone(), script.clone());
+		Ok((hash, script))
+	}
+
+	pub fn get(&self, hash: &str) -> Option<Arc<AST>> {
+		self.scripts.read().unwrap().get(hash).cloned()
+	}
+}
+
+/// Runs `script` with `keys` and `args` in scope and returns what it evaluates to.
+///
+/// The script reaches the cache with `cache(action, data)`, which takes the same
+/// action names and payloads as WebSocket and TCP clients and fails the script
+/// when the action does. The caller holds the transaction locks of the shards of
+/// `keys`, so nothing else runs on them in between, and the script may only use
+/// those keys. Changes made before a failure are kept.
+pub fn run(
+	state: Arc<SharedState>,
+	script: &AST,
+	keys: Vec<String>,
+	args: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, Error> {
+	let mut engine: Engine = engine();
+	let deadline: Instant = Instant::now() + state.scripts.timeout;
+	engine.on_progress(move |_| (Instant::now() > deadline).then_some(Dynamic::UNIT));
+	let call_state: Arc<SharedState> = state.clone();
+	let call_keys: Arc<[String]> = keys.clone().into();
+	engine.register_fn(
+		"cache",
+		move |action: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+			call(call_state.clone(), &call_keys, action, Dynamic::UNIT)
+		},
+	);
+	let call_keys: Arc<[String]> = keys.clone().into();
+	engine.register_fn(
+		"cache",
+		move |action: &str, data: Dynamic| -> Result<Dynamic, Box<EvalAltResult>> {
+			call(state.clone(), &call_keys, action, data)
+		},
+	);
+
+	let mut scope: rhai::Scope = rhai::Scope::new();
+	scope.push("keys", to_dynamic(keys)?);
+	scope.push("args", to_dynamic(args)?);
+
+	// The script may take a while, other tasks move to another worker meanwhile.
+	let result: Dynamic =
+		tokio::task::block_in_place(|| engine.eval_ast_with_scope(&mut scope, script)).map_err(
+			|err| match *err {
+				EvalAltResult::ErrorTerminated(..) => {
+					script_failed("Script took longer than the time limit!")
+				}
+				// Errors of `cache` are passed on as they are.
+				EvalAltResult::ErrorRuntime(value, _) => match rhai::serde::from_dynamic(&value) {
+					Ok(error) => error,
+					Err(_) => script_failed(&value.to_string()),
+				},
+				err => script_failed(&err.to_string()),
+			},
+		)?;
+	rhai::serde::from_dynamic(&result).map_err(|err| script_failed(&err.to_string()))
+}
+
+/// An engine with limits on what a script can allocate, so it cannot use up
+/// the memory of the server before the time limit stops it.
+fn engine() -> Engine {
+	let mut engine: Engine = Engine::new();
+	engine
+		.set_max_string_size(MAX_STRING_SIZE)
+		.set_max_array_size(MAX_COLLECTION_SIZE)
+		.set_max_map_size(MAX_COLLECTION_SIZE)
+		.set_max_call_levels(MAX_CALL_LEVELS)
+		.set_max_expr_depths(MAX_EXPR_DEPTHS.0, MAX_EXPR_DEPTHS.1)
+		.set_max_operations(MAX_OPERATIONS);
+	engine
+}
+
+fn call(
+	state: Arc<SharedState>,
+	keys: &[String],
+	action: &str,
+	data: Dynamic,
+) -> Result<Dynamic, Box<EvalAltResult>> {
+	let action: Actions = serde_json::from_value(serde_json::Value::from(action))
+		.map_err(|_| format!("Unknown action: {}", action))?;
+	if let Actions::EVAL | Actions::EVALSHA = action {
+		return Err("Scripts cannot run other scripts".into());
+	}
+
+	let data: serde_json::Value = rhai::serde::from_dynamic(&data)?;
+	match dispatch::scope(&action, &data) {
+		Scope::Keys(used) => {
+			if let Some(key) = used.iter().find(|key| !keys.contains(key)) {
+				return Err(format!("Key {} is not in the keys of the script", key).into());
+			}
+		}
+		Scope::All => return Err("Scripts cannot run actions on the whole cache".into()),
+	}
+	match dispatch::run(state, action, data) {
+		Ok(result) => rhai::serde::to_dynamic(result),
+		Err(error) => {
+			let error: Dynamic = rhai::serde::to_dynamic(error)?;
+			Err(EvalAltResult::ErrorRuntime(error, rhai::Position::NONE).into())
+		}
+	}
+}
+
+fn to_dynamic<T: serde::Serialize>(value: T) -> Result<Dynamic, Error> {
+	rhai::serde::to_dynamic(value).map_err(|err| script_failed(&err.to_string()))
+}
+
+fn script_failed(message: &str) -> Error {
+	Error::new(ErrorCode::ScriptFailed as u64, message)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::cache::Cache;
+	use crate::caches::eviction::Limits;
+	use crate::caches::snapshot::SnapshotOptions;
+
+	fn eval(state: &Arc<SharedState>, source: &str) -> Result<serde_json::Value, Error> {
+		let (_, script) = state.scripts.load(source)?;
+		run(state.clone(), &script, vec!["h".to_string()], Vec::new())
+	}
+
+	fn state() -> Arc<SharedState> {
+		SharedState::for_test(Cache::new(
+			String::new(),
+			false,
+			SnapshotOptions::default(),
+			Limits::default(),
+			4,
+		))
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn returns_values_shaped_like_errors() {
+		let state: Arc<SharedState> = state();
+		let result = eval(
+			&state,
+			r#"
+				cache("HSET", #{ key: keys[0], fields: #{ code: 1011, message: "value" } });
+				cache("HGETALL", #{ key: keys[0] })
+			"#,
+		);
+		assert_eq!(
+			result.unwrap(),
+			serde_json::json!({ "code": 1011, "message": "value" })
+		);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn fails_with_the_error_of_an_action() {
+		let state: Arc<SharedState> = state();
+		let result = eval(
+			&state,
+			r#"
+				cache("SET", #{ key: keys[0], value: 1, ttl: 0 });
+				cache("HGETALL", #{ key: keys[0] })
+			"#,
+		);
+		assert_eq!(result.unwrap_err().code, ErrorCode::WrongType as u64);
+	}
+}
@@ -1,8 +1,80 @@
 use crate::caches::cache::Cache;
-use std::sync::{atomic::AtomicU64, Mutex};
+use crate::pubsub::PubSub;
+use crate::scripts::Scripts;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use tokio::sync::{watch, RwLockReadGuard, RwLockWriteGuard};
+
+/// Keys an operation or a transaction works on, see `SharedState::operation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+	Keys(Vec<String>),
+	/// Everything, for actions on the whole cache
+	All,
+}
 
 pub struct SharedState {
 	pub token: String,
-	pub cache: Mutex<Cache>,
+	pub cache: Cache,
 	pub ws_connections: AtomicU64,
+	pub compacting: AtomicBool,
+	pub shutdown: watch::Sender<bool>,
+	pub scripts: Scripts,
+	pub pubsub: PubSub,
+}
+
+impl SharedState {
+	/// Resolves once a graceful shutdown has been requested.
+	pub async fn shutdown_requested(&self) {
+		let mut shutdown: watch::Receiver<bool> = self.shutdown.subscribe();
+		shutdown.wait_for(|stopping| *stopping).await.ok();
+	}
+
+	/// Waits until no transaction runs on the shards of `scope` and keeps others
+	/// from starting there.
+	pub async fn operation(&self, scope: &Scope) -> Vec<RwLockReadGuard<'_, ()>> {
+		let mut guards: Vec<RwLockReadGuard<()>> = Vec::new();
+		for index in self.shards(scope) {
+			guards.push(self.cache.transaction_lock(index).read().await);
+		}
+		guards
+	}
+
+	/// Waits until all running operations on the shards of `scope` are done and
+	/// keeps new ones from starting there.
+	pub async fn transaction(&self, scope: &Scope) -> Vec<RwLockWriteGuard<'_, ()>> {
+		let mut guards: Vec<RwLockWriteGuard<()>> = Vec::new();
+		for index in self.shards(scope) {
+			guards.push(self.cache.transaction_lock(index).write().await);
+		}
+		guards
+	}
+
+	/// Shards of `scope` in index order, the order their locks are taken in.
+	fn shards(&self, scope: &Scope) -> Vec<usize> {
+		match scope {
+			Scope::Keys(keys) => {
+				let mut indices: Vec<usize> = keys.iter().map(|key| self.cache.index(key)).collect();
+				indices.sort_unstable();
+				indices.dedup();
+				indices
+			}
+			Scope::All => (0..self.cache.shard_count()).collect(),
+		}
+	}
+}
+
+#[cfg(test)]
+impl SharedState {
+	/// A state around `cache` with nothing running in the background.
+	pub fn for_test(cache: Cache) -> std::sync::Arc<SharedState> {
+		std::sync::Arc::new(SharedState {
+			token: "token".to_string(),
+			ws_connections: AtomicU64::new(0),
+			compacting: AtomicBool::new(false),
+			shutdown: watch::channel(false).0,
+			scripts: Scripts::new(std::time::Duration::from_secs(1)),
+			pubsub: PubSub::default(),
+			cache,
+		})
+	}
 }
@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::caches::cache::Cache;
+use crate::caches::events::{Event, KeyEvent};
+use crate::caches::snapshot::{self, SnapshotOptions};
+use crate::caches::stats::SaveStatus;
+use crate::state::SharedState;
+use crate::utils::current_time;
+
+/// Snapshot rule given as `<seconds>:<writes>`: save when at least `writes`
+/// changes happened and the last snapshot is at least `seconds` old.
+#[derive(Debug, Clone, Copy)]
+pub struct SaveRule {
+	pub seconds: u64,
+	pub writes: u64,
+}
+
+impl FromStr for SaveRule {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (seconds, writes) = s
+			.split_once(':')
+			.ok_or_else(|| format!("expected <seconds>:<writes>, got '{}'", s))?;
+		Ok(SaveRule {
+			seconds: seconds
+				.trim()
+				.parse()
+				.map_err(|_| format!("invalid seconds '{}'", seconds))?,
+			writes: writes
+				.trim()
+				.parse()
+				.map_err(|_| format!("invalid writes '{}'", writes))?,
+		})
+	}
+}
+
+/// Flushes the append-only log to disk once per second when the `everysec` policy is used.
+pub async fn fsync_aof(state: Arc<SharedState>) {
+	let mut interval = tokio::time::interval(Duration::from_secs(1));
+	loop {
+		interval.tick().await;
+
+		let pending: Option<io::Result<File>> = match state.cache.persistence().aof.as_mut() {
+			Some(aof) => aof.pending_sync(),
+			None => None,
+		};
+
+		let result: io::Result<()> = match pending {
+			Some(Ok(file)) => tokio::task::spawn_blocking(move || file.sync_data())
+				.await
+				.unwrap_or_else(|err| Err(io::Error::other(err))),
+			Some(Err(err)) => Err(err),
+			None => Ok(()),
+		};
+
+		if let Err(err) = result {
+			eprintln!("Failed to fsync append-only log: {}", err);
+			if let Some(aof) = state.cache.persistence().aof.as_mut() {
+				aof.sync_failed();
+			}
+		}
+	}
+}
+
+/// Starts a background compaction unless one is already running.
+pub fn start_compaction(state: Arc<SharedState>) -> bool {
+	if state.compacting.swap(true, Ordering::AcqRel) {
+		return false;
+	}
+
+	tokio::spawn(async move {
+		let started: u128 = current_time();
+		match compact(state.clone()).await {
+			Ok(true) => {
+				state.cache.record_save(started, true);
+				println!("Compaction finished");
+			}
+			Ok(false) => println!("Compaction discarded, a newer snapshot was saved meanwhile"),
+			Err(err) => {
+				state.cache.record_save(started, false);
+				eprintln!("Compaction failed: {}", err);
+			}
+		}
+		state.compacting.store(false, Ordering::Release);
+	});
+	true
+}
+
+/// Rewrites the snapshot from a copy of the cache and drops the append-only
+/// entries it now covers. Writers are only held off while the copy is taken,
+/// not while the snapshot is serialized and written.
+async fn compact(state: Arc<SharedState>) -> io::Result<bool> {
+	let (generation, cache_map) = state.cache.begin_compaction()?;
+	let path: PathBuf = state.cache.compaction_path();
+	let options: SnapshotOptions = state.cache.snapshot_options;
+
+	let size: u64 =
+		tokio::task::spawn_blocking(move || snapshot::write_file(&path, &cache_map, options))
+			.await
+			.map_err(io::Error::other)??;
+
+	state.cache.finish_compaction(generation, size)
+}
+
+/// Compacts the append-only log once it grows past `min_size` bytes and
+/// `percentage` percent of the snapshot size.
+pub async fn auto_compact(state: Arc<SharedState>, percentage: u64, min_size: u64) {
+	let mut interval = tokio::time::interval(Duration::from_secs(1));
+	loop {
+		interval.tick().await;
+
+		let needed: bool = state.cache.needs_compaction(percentage, min_size);
+		if needed && start_compaction(state.clone()) {
+			println!("Append-only log grew too large, compacting");
+		}
+	}
+}
+
+/// Takes a background snapshot whenever one of the save rules matches.
+pub async fn auto_save(state: Arc<SharedState>, rules: Vec<SaveRule>) {
+	if rules.is_empty() {
+		return;
+	}
+
+	let started: u128 = current_time();
+	let mut last_attempt: u128 = 0;
+	let mut interval = tokio::time::interval(Duration::from_secs(1));
+	loop {
+		interval.tick().await;
+
+		let changes: u64 = state.cache.changes_since_save();
+		let (since_save, failed) = {
+			let persistence = state.cache.persistence();
+			let last_save: u128 = persistence.last_save_time.max(started);
+			(
+				current_time().saturating_sub(last_save) / 1000,
+				persistence.last_save_status == SaveStatus::Failed,
+			)
+		};
+
+		// After a failed save, wait a little before trying again.
+		if failed && current_time() - last_attempt < 5000 {
+			continue;
+		}
+
+		let matched: Option<&SaveRule> = rules
+			.iter()
+			.find(|rule| changes > 0 && changes >= rule.writes && since_save >= rule.seconds as u128);
+		if let Some(rule) = matched {
+			if start_compaction(state.clone()) {
+				last_attempt = current_time();
+				println!("{} changes in {} seconds, saving", changes, rule.seconds);
+			}
+		}
+	}
+}
+
+/// Keys removed per expiry round.
+const EXPIRE_BATCH: usize = 20;
+/// Longest time a single expiry cycle may run.
+const EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+
+/// Removes expired keys in the background, so keys that are never read again
+/// do not stay in memory. Each round removes a small batch from one shard under
+/// a short lock and another round follows while full batches keep coming back.
+pub async fn active_expire(state: Arc<SharedState>) {
+	let mut interval = tokio::time::interval(Duration::from_millis(100));
+	loop {
+		interval.tick().await;
+
+		let started: Instant = Instant::now();
+		for index in 0..state.cache.shard_count() {
+			while expire_batch(&state.cache, index) == EXPIRE_BATCH && started.elapsed() < EXPIRE_BUDGET {
+				tokio::task::yield_now().await;
+			}
+		}
+	}
+}
+
+/// Removes a batch of expired keys from shard `index` and returns how many
+/// there were. A shard with a running transaction is left alone, the keys it
+/// works on must not change under it.
+fn expire_batch(cache: &Cache, index: usize) -> usize {
+	match cache.transaction_lock(index).try_read() {
+		Ok(_transaction) => cache.expire_shard(index, EXPIRE_BATCH),
+		Err(_) => 0,
+	}
+}
+
+/// Publishes every change to a key twice, on `__keyspace__:<key>` with the event
+/// as message and on `__keyevent__:<event>` with the key as message, so clients
+/// can follow a key or a prefix with SUBSCRIBE and PSUBSCRIBE. A flush is only
+/// published on `__keyevent__:flush`.
+pub async fn keyspace_notifications(state: Arc<SharedState>) {
+	let mut events: broadcast::Receiver<KeyEvent> = state.cache.events();
+	loop {
+		match events.recv().await {
+			Ok(KeyEvent { event, key }) => {
+				let pubsub = &state.pubsub;
+				if event != Event::Flush {
+					pubsub.publish(&format!("__keyspace__:{}", key), event.name().into());
+				}
+				pubsub.publish(&format!("__keyevent__:{}", event.name()), key.into());
+			}
+			Err(broadcast::error::RecvError::Lagged(missed)) => {
+				eprintln!(
+					"Keyspace notifications fell behind, {} events were dropped",
+					missed
+				);
+			}
+			Err(broadcast::error::RecvError::Closed) => break,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::eviction::Limits;
+
+	#[test]
+	fn expires_keys_unless_a_transaction_runs_on_them() {
+		let cache: Cache = Cache::new(
+			String::new(),
+			false,
+			SnapshotOptions::default(),
+			Limits::default(),
+			1,
+		);
+		for key in ["a", "b"] {
+			cache.write(key).set(key.to_string(), 1.into(), 1).unwrap();
+		}
+		cache.write("c").set("c".to_string(), 1.into(), 0).unwrap();
+		std::thread::sleep(Duration::from_millis(5));
+
+		let transaction = cache.transaction_lock(0).try_write().unwrap();
+		assert_eq!(expire_batch(&cache, 0), 0);
+		assert_eq!(cache.key_count(), 3);
+
+		drop(transaction);
+		assert_eq!(expire_batch(&cache, 0), 2);
+		assert_eq!(cache.key_count(), 1);
+	}
+}
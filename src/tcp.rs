@@ -1,12 +1,17 @@
 use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 
-use crate::error::ErrorCode;
+use crate::endpoints::transaction::Transaction;
+use crate::endpoints::ws::WsResponse;
+use crate::error::{Error, ErrorCode};
+use crate::pubsub::Subscriber;
 use crate::state::SharedState;
-use crate::types::{Actions, DataPayload, KeyPayload, ListPayload, NumberDataPayload};
+use crate::types::Actions;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Payload {
@@ -15,132 +20,78 @@ pub struct Payload {
 	pub data: serde_json::Value,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct TcpResponse {
-	pub id: u64,
-	pub code: u64,
-	pub data: Option<serde_json::Value>,
-}
+/// Largest request accepted, like the default body limit of the HTTP endpoints.
+const MAX_REQUEST_SIZE: u64 = 2 * 1024 * 1024;
 
+/// Serves an authenticated client. Requests and responses are JSON documents
+/// that each end with a newline. Responses are sent in the order of the requests
+/// as `WsResponse`, like on WebSocket, and pushes of subscriptions are sent in
+/// between.
 pub async fn handle_client(stream: &mut TcpStream, state: Arc<SharedState>) {
-	let mut buffer: [u8; 512] = [0; 512];
+	let (reader, mut writer) = stream.split();
+	let mut reader: BufReader<ReadHalf> = BufReader::new(reader);
+	let mut line: Vec<u8> = Vec::new();
+	let mut transaction: Transaction = Transaction::default();
+	let mut subscriber: Subscriber = Subscriber::new(state.clone());
 	loop {
-		match stream.read(&mut buffer).await {
-			Ok(n) => {
-				if n == 0 {
+		// Bytes read before another branch completes stay in `line`.
+		let limit: u64 = MAX_REQUEST_SIZE.saturating_sub(line.len() as u64);
+		let mut request = (&mut reader).take(limit);
+		let read = tokio::select! {
+			read = request.read_until(b'\n', &mut line) => read,
+			push = subscriber.next() => {
+				if write_line(&mut writer, &serde_json::to_string(&push).unwrap()).await.is_err() {
 					break;
 				}
-				if let Ok(payload) = serde_json::from_slice::<Payload>(&buffer[..n]) {
-					let response = match payload.action {
-						Actions::PING => super::endpoints::v1::ping::handle_ws(),
-						Actions::STATS => super::endpoints::v1::stats::handle_ws(state.clone()),
-						Actions::SAVE => super::endpoints::v1::save::handle_ws(state.clone()),
-						Actions::CLEAN => super::endpoints::v1::clean::handle_ws(state.clone()),
-						Actions::FLUSH => super::endpoints::v1::flush::handle_ws(state.clone()),
-						Actions::GET => {
-							if let Ok(data) = serde_json::from_value::<KeyPayload>(payload.data) {
-								super::endpoints::v1::get::handle_ws(state.clone(), data.key)
-							} else {
-								serde_json::to_value(TcpResponse {
-									id: payload.id,
-									code: ErrorCode::InvalidData as u64,
-									data: None,
-								})
-								.unwrap()
-							}
-						}
-						Actions::SET => {
-							if let Ok(data) = serde_json::from_value::<DataPayload>(payload.data) {
-								super::endpoints::v1::set::handle_ws(state.clone(), data.key, data.value, data.ttl)
-							} else {
-								serde_json::to_value(TcpResponse {
-									id: payload.id,
-									code: ErrorCode::InvalidData as u64,
-									data: None,
-								})
-								.unwrap()
-							}
-						}
-						Actions::DEL => {
-							if let Ok(data) = serde_json::from_value::<KeyPayload>(payload.data) {
-								super::endpoints::v1::del::handle_ws(state.clone(), data.key)
-							} else {
-								serde_json::to_value(TcpResponse {
-									id: payload.id,
-									code: ErrorCode::InvalidData as u64,
-									data: None,
-								})
-								.unwrap()
-							}
-						}
-						Actions::LIST => {
-							if let Ok(data) = serde_json::from_value::<ListPayload>(payload.data) {
-								super::endpoints::v1::list::handle_ws(
-									state.clone(),
-									data.prefix,
-									data.limit,
-									data.cursor,
-								)
-							} else {
-								serde_json::to_value(TcpResponse {
-									id: payload.id,
-									code: ErrorCode::InvalidData as u64,
-									data: None,
-								})
-								.unwrap()
-							}
-						}
-						Actions::EXISTS => {
-							if let Ok(data) = serde_json::from_value::<KeyPayload>(payload.data) {
-								super::endpoints::v1::exists::handle_ws(state.clone(), data.key)
-							} else {
-								serde_json::to_value(TcpResponse {
-									id: payload.id,
-									code: ErrorCode::InvalidData as u64,
-									data: None,
-								})
-								.unwrap()
-							}
-						}
-						Actions::INCR => {
-							if let Ok(data) = serde_json::from_value::<NumberDataPayload>(payload.data) {
-								super::endpoints::v1::incr::handle_ws(state.clone(), data.key, data.value, data.ttl)
-							} else {
-								serde_json::to_value(TcpResponse {
-									id: payload.id,
-									code: ErrorCode::InvalidData as u64,
-									data: None,
-								})
-								.unwrap()
-							}
-						}
-						Actions::DECR => {
-							if let Ok(data) = serde_json::from_value::<NumberDataPayload>(payload.data) {
-								super::endpoints::v1::decr::handle_ws(state.clone(), data.key, data.value, data.ttl)
-							} else {
-								serde_json::to_value(TcpResponse {
-									id: payload.id,
-									code: ErrorCode::InvalidData as u64,
-									data: None,
-								})
-								.unwrap()
-							}
-						}
-					};
-					let response_json: String = serde_json::to_string(&response).unwrap();
-					stream.write_all(response_json.as_bytes()).await.unwrap();
-				} else {
-					println!("Received invalid JSON data");
-				}
+				continue;
 			}
+			_ = state.shutdown_requested() => break,
+		};
+		match read {
+			Ok(_) if line.last() == Some(&b'\n') => {}
+			Ok(_) if line.len() as u64 >= MAX_REQUEST_SIZE => {
+				println!(
+					"Request larger than {} bytes, closing connection",
+					MAX_REQUEST_SIZE
+				);
+				break;
+			}
+			// The connection was closed, an unfinished request is dropped.
+			Ok(_) => break,
 			Err(e) => {
 				println!("Error reading from socket: {}", e);
 				break;
 			}
 		}
+
+		let response: String = match serde_json::from_slice::<Payload>(&line) {
+			Ok(payload) => {
+				let result: Result<serde_json::Value, Error> = match payload.action {
+					Actions::SUBSCRIBE
+					| Actions::UNSUBSCRIBE
+					| Actions::PSUBSCRIBE
+					| Actions::PUNSUBSCRIBE => subscriber.execute(payload.action, payload.data),
+					action => {
+						transaction
+							.execute(state.clone(), payload.id, action, payload.data)
+							.await
+					}
+				};
+				serde_json::to_string(&WsResponse::new(payload.id, result)).unwrap()
+			}
+			Err(_) => serde_json::to_string(&Error::from_code(ErrorCode::InvalidPayload)).unwrap(),
+		};
+		line.clear();
+		if write_line(&mut writer, &response).await.is_err() {
+			break;
+		}
 	}
 }
 
+async fn write_line(writer: &mut WriteHalf<'_>, message: &str) -> io::Result<()> {
+	writer.write_all(format!("{}\n", message).as_bytes()).await
+}
+
 pub async fn authenticate(stream: &mut TcpStream, token: &str) -> bool {
 	let mut buffer = vec![0u8; 1024];
 
@@ -184,3 +135,108 @@ pub async fn authenticate(stream: &mut TcpStream, token: &str) -> bool {
 
 	false
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::caches::cache::Cache;
+	use crate::caches::eviction::Limits;
+	use crate::caches::snapshot::SnapshotOptions;
+	use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+	use tokio::net::TcpListener;
+
+	struct Client {
+		reader: BufReader<OwnedReadHalf>,
+		writer: OwnedWriteHalf,
+	}
+
+	impl Client {
+		async fn send(&mut self, text: &str) {
+			self.writer.write_all(text.as_bytes()).await.unwrap();
+		}
+
+		async fn receive(&mut self) -> serde_json::Value {
+			let mut line: String = String::new();
+			self.reader.read_line(&mut line).await.unwrap();
+			assert!(line.ends_with('\n'));
+			serde_json::from_str(&line).unwrap()
+		}
+	}
+
+	/// Serves a single client on a loopback socket and connects to it.
+	async fn connect() -> (Arc<SharedState>, Client) {
+		let state: Arc<SharedState> = SharedState::for_test(Cache::new(
+			String::new(),
+			false,
+			SnapshotOptions::default(),
+			Limits::default(),
+			4,
+		));
+		let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let address = listener.local_addr().unwrap();
+		let server_state: Arc<SharedState> = state.clone();
+		tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+			handle_client(&mut stream, server_state).await;
+		});
+
+		let (reader, writer) = TcpStream::connect(address).await.unwrap().into_split();
+		let client: Client = Client {
+			reader: BufReader::new(reader),
+			writer,
+		};
+		(state, client)
+	}
+
+	#[tokio::test]
+	async fn answers_requests_in_envelopes() {
+		let (_, mut client) = connect().await;
+		client
+			.send("{\"id\":1,\"action\":\"SET\",\"data\":{\"key\":\"a\",\"value\":7,\"ttl\":0}}\n")
+			.await;
+		assert_eq!(
+			client.receive().await,
+			serde_json::json!({ "id": 1, "code": 0, "data": null })
+		);
+
+		// A request may arrive in parts, it runs once its newline does.
+		client.send("{\"id\":2,\"action\":\"GET\",").await;
+		client.send("\"data\":{\"key\":\"a\"}}\n").await;
+		let response: serde_json::Value = client.receive().await;
+		assert_eq!(response["id"], 2);
+		assert_eq!(response["code"], 0);
+		assert_eq!(response["data"]["value"], 7);
+
+		client
+			.send("{\"id\":3,\"action\":\"INCR\",\"data\":{\"key\":\"a\"}}\n")
+			.await;
+		assert_eq!(
+			client.receive().await,
+			serde_json::json!({ "id": 3, "code": ErrorCode::InvalidData as u64, "data": null })
+		);
+
+		client.send("not json\n").await;
+		assert_eq!(
+			client.receive().await,
+			serde_json::to_value(Error::from_code(ErrorCode::InvalidPayload)).unwrap()
+		);
+	}
+
+	#[tokio::test]
+	async fn sends_pushes_apart_from_responses() {
+		let (state, mut client) = connect().await;
+		client
+			.send("{\"id\":1,\"action\":\"SUBSCRIBE\",\"data\":{\"channels\":[\"news\"]}}\n")
+			.await;
+		assert_eq!(
+			client.receive().await,
+			serde_json::json!({ "id": 1, "code": 0, "data": 1 })
+		);
+
+		assert_eq!(state.pubsub.publish("news", serde_json::json!("hello")), 1);
+		assert_eq!(
+			client.receive().await,
+			serde_json::json!({ "push": "message", "channel": "news", "message": "hello" })
+		);
+	}
+}
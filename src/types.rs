@@ -1,15 +1,86 @@
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
+use crate::caches::zset::ScoredMember;
+use crate::error::ErrorCode;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Actions {
 	GET,
 	SET,
 	DEL,
+	SETNX,
+	SETXX,
+	GETSET,
+	CAS,
+	MGET,
+	MSET,
+	MDEL,
+	MEXISTS,
 	LIST,
+	EXPIRING,
 	EXISTS,
 	INCR,
 	DECR,
+	INCRBYFLOAT,
+	DECRBYFLOAT,
+	TTL,
+	PTTL,
+	EXPIRE,
+	PEXPIRE,
+	EXPIREAT,
+	PERSIST,
+	HSET,
+	HGET,
+	HDEL,
+	HGETALL,
+	HINCRBY,
+	HEXISTS,
+	HLEN,
+	HKEYS,
+	LPUSH,
+	RPUSH,
+	LPOP,
+	RPOP,
+	LRANGE,
+	LLEN,
+	LTRIM,
+	LINDEX,
+	BLPOP,
+	SADD,
+	SREM,
+	SISMEMBER,
+	SMEMBERS,
+	SCARD,
+	SPOP,
+	SRANDMEMBER,
+	SINTER,
+	SUNION,
+	SDIFF,
+	ZADD,
+	ZINCRBY,
+	ZSCORE,
+	ZRANK,
+	ZRANGE,
+	ZRANGEBYSCORE,
+	ZREM,
+	ZREMRANGEBYSCORE,
+	ZCARD,
+	MULTI,
+	EXEC,
+	DISCARD,
+	WATCH,
+	UNWATCH,
+	EVAL,
+	EVALSHA,
+	SCRIPTLOAD,
+	PUBLISH,
+	SUBSCRIBE,
+	UNSUBSCRIBE,
+	PSUBSCRIBE,
+	PUNSUBSCRIBE,
 	SAVE,
+	COMPACT,
 	CLEAN,
 	FLUSH,
 	PING,
@@ -28,6 +99,22 @@ pub struct DataPayload {
 	pub ttl: u64,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MultiSetPayload {
+	pub items: Vec<DataPayload>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CasPayload {
+	pub key: String,
+	pub value: serde_json::Value,
+	pub ttl: u64,
+	/// Version the key must still have, 0 when it must not exist
+	pub version: Option<u64>,
+	/// Value the key must still hold
+	pub expected: Option<serde_json::Value>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ListPayload {
 	pub prefix: String,
@@ -35,9 +122,316 @@ pub struct ListPayload {
 	pub cursor: usize,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExpiringPayload {
+	pub seconds: u64,
+	pub limit: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NumberDataPayload {
 	pub key: String,
 	pub value: i64,
 	pub ttl: u64,
+	#[serde(flatten)]
+	pub options: CounterOptions,
+}
+
+/// Optional settings of INCR and DECR.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct CounterOptions {
+	/// Value a missing key starts at before the change is applied, 0 by default
+	pub initial: Option<i64>,
+	pub min: Option<i64>,
+	pub max: Option<i64>,
+	/// Whether a result outside of `min` and `max` is moved to the nearest
+	/// bound instead of being rejected
+	#[serde(default)]
+	pub clamp: bool,
+}
+
+impl CounterOptions {
+	/// Checks `value` against the bounds, clamping it when requested.
+	pub fn bound(&self, value: i64) -> Result<i64, ErrorCode> {
+		let min: i64 = self.min.unwrap_or(i64::MIN);
+		let max: i64 = self.max.unwrap_or(i64::MAX);
+		if min > max {
+			return Err(ErrorCode::InvalidData);
+		}
+		if (min..=max).contains(&value) {
+			Ok(value)
+		} else if self.clamp {
+			Ok(value.clamp(min, max))
+		} else {
+			Err(ErrorCode::OutOfRange)
+		}
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FloatDataPayload {
+	pub key: String,
+	pub value: f64,
+	pub ttl: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExpirePayload {
+	pub key: String,
+	pub ttl: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExpireAtPayload {
+	pub key: String,
+	pub timestamp: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HashSetPayload {
+	pub key: String,
+	pub fields: IndexMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FieldPayload {
+	pub key: String,
+	pub field: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FieldsPayload {
+	pub key: String,
+	pub fields: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FieldNumberPayload {
+	pub key: String,
+	pub field: String,
+	pub value: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushPayload {
+	pub key: String,
+	pub values: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RangePayload {
+	pub key: String,
+	pub start: i64,
+	pub stop: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexPayload {
+	pub key: String,
+	pub index: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockingPopPayload {
+	pub keys: Vec<String>,
+	pub timeout: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberPayload {
+	pub key: String,
+	pub member: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MembersPayload {
+	pub key: String,
+	pub members: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CountPayload {
+	pub key: String,
+	pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeysPayload {
+	pub keys: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScoredMembersPayload {
+	pub key: String,
+	pub members: Vec<ScoredMember>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberScorePayload {
+	pub key: String,
+	pub member: String,
+	pub value: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScoreRangePayload {
+	pub key: String,
+	pub min: f64,
+	pub max: f64,
+	pub offset: Option<usize>,
+	pub count: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScoreBoundsPayload {
+	pub key: String,
+	pub min: f64,
+	pub max: f64,
+}
+
+/// An action queued in a transaction, `data` is the payload it normally takes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueuedAction {
+	#[serde(default)]
+	pub id: u64,
+	pub action: Actions,
+	#[serde(default)]
+	pub data: serde_json::Value,
+}
+
+/// A key and the version it was seen at, 0 when it did not exist.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchedKey {
+	pub key: String,
+	pub version: u64,
+	/// `Shard::removals` of the shard of the key when WATCH saw it
+	#[serde(skip)]
+	pub removals: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransactionPayload {
+	#[serde(default)]
+	pub watch: Vec<WatchedKey>,
+	pub actions: Vec<QueuedAction>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScriptPayload {
+	pub script: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EvalPayload {
+	pub script: String,
+	#[serde(default)]
+	pub keys: Vec<String>,
+	#[serde(default)]
+	pub args: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EvalShaPayload {
+	pub hash: String,
+	#[serde(default)]
+	pub keys: Vec<String>,
+	#[serde(default)]
+	pub args: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublishPayload {
+	pub channel: String,
+	pub message: serde_json::Value,
+}
+
+/// Channels to (un)subscribe, or glob patterns for PSUBSCRIBE and PUNSUBSCRIBE.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelsPayload {
+	#[serde(default, alias = "patterns")]
+	pub channels: Vec<String>,
+}
+
+/// Keys to watch, `key` alone, all keys starting with `prefix`, or every key when
+/// neither is given.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WatchPayload {
+	pub key: Option<String>,
+	pub prefix: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PollPayload {
+	pub key: String,
+	/// Version the caller already has, 0 when it has none
+	pub version: u64,
+	pub timeout: u64,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn bounds(min: Option<i64>, max: Option<i64>, clamp: bool) -> CounterOptions {
+		CounterOptions {
+			initial: None,
+			min,
+			max,
+			clamp,
+		}
+	}
+
+	#[test]
+	fn accepts_anything_without_bounds() {
+		let options: CounterOptions = CounterOptions::default();
+		assert!(matches!(options.bound(i64::MIN), Ok(i64::MIN)));
+		assert!(matches!(options.bound(0), Ok(0)));
+		assert!(matches!(options.bound(i64::MAX), Ok(i64::MAX)));
+	}
+
+	#[test]
+	fn accepts_values_within_bounds() {
+		let options: CounterOptions = bounds(Some(-5), Some(5), false);
+		assert!(matches!(options.bound(-5), Ok(-5)));
+		assert!(matches!(options.bound(0), Ok(0)));
+		assert!(matches!(options.bound(5), Ok(5)));
+	}
+
+	#[test]
+	fn rejects_values_out_of_bounds() {
+		let options: CounterOptions = bounds(Some(-5), Some(5), false);
+		assert!(matches!(options.bound(-6), Err(ErrorCode::OutOfRange)));
+		assert!(matches!(options.bound(6), Err(ErrorCode::OutOfRange)));
+		assert!(matches!(
+			bounds(None, Some(0), false).bound(1),
+			Err(ErrorCode::OutOfRange)
+		));
+		assert!(matches!(
+			bounds(Some(0), None, false).bound(-1),
+			Err(ErrorCode::OutOfRange)
+		));
+	}
+
+	#[test]
+	fn clamps_values_out_of_bounds() {
+		let options: CounterOptions = bounds(Some(-5), Some(5), true);
+		assert!(matches!(options.bound(-6), Ok(-5)));
+		assert!(matches!(options.bound(i64::MIN), Ok(-5)));
+		assert!(matches!(options.bound(6), Ok(5)));
+		assert!(matches!(options.bound(3), Ok(3)));
+		assert!(matches!(bounds(Some(0), None, true).bound(-1), Ok(0)));
+	}
+
+	#[test]
+	fn rejects_min_above_max() {
+		assert!(matches!(
+			bounds(Some(1), Some(0), false).bound(0),
+			Err(ErrorCode::InvalidData)
+		));
+		assert!(matches!(
+			bounds(Some(1), Some(0), true).bound(0),
+			Err(ErrorCode::InvalidData)
+		));
+		assert!(matches!(bounds(Some(3), Some(3), false).bound(3), Ok(3)));
+	}
 }